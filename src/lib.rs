@@ -1,4 +1,49 @@
-use redis::{Cmd, ToRedisArgs};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::marker::PhantomData;
+#[cfg(feature = "pool")]
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use redis::{
+    Arg, Cmd, Connection, ConnectionLike, ErrorKind, FromRedisValue, PubSub,
+    RedisError, RedisResult, RedisWrite, ToRedisArgs, Value,
+};
+
+/// Value types as reported by `TYPE`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RedisType {
+    /// The key doesn't exist.
+    None,
+    String,
+    List,
+    Set,
+    ZSet,
+    Hash,
+    Stream,
+    /// Types added by modules, e.g. `ReJSON-RL`.
+    Other(String),
+}
+
+impl FromRedisValue for RedisType {
+    fn from_redis_value(v: &Value) -> RedisResult<RedisType> {
+        let name = String::from_redis_value(v)?;
+        Ok(match name.as_str() {
+            "none" => RedisType::None,
+            "string" => RedisType::String,
+            "list" => RedisType::List,
+            "set" => RedisType::Set,
+            "zset" => RedisType::ZSet,
+            "hash" => RedisType::Hash,
+            "stream" => RedisType::Stream,
+            _ => RedisType::Other(name),
+        })
+    }
+}
 
 pub trait Key<K: ToRedisArgs> {
     fn new(key: K) -> Self;
@@ -10,6 +55,42 @@ pub trait Key<K: ToRedisArgs> {
     {
         Cmd::del(self.key())
     }
+
+    /// Convert to another key wrapper if the server holds the matching type.
+    ///
+    /// Runs `TYPE` and returns `None` if the key holds a different type.
+    /// A missing key can be written as any type, so it's always converted.
+    #[allow(clippy::wrong_self_convention)]
+    fn as_checked<T: Key<K> + Typed>(self, con: &mut dyn ConnectionLike)
+        -> RedisResult<Option<T>>
+        where Self: Sized
+    {
+        let key = self.key();
+        let actual: RedisType = redis::cmd("TYPE").arg(&key).query(con)?;
+        if actual == RedisType::None || actual == T::REDIS_TYPE {
+            Ok(Some(T::new(key)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Key wrappers that lend out their key without being consumed.
+pub trait KeyRef<K: ToRedisArgs>: Key<K> {
+    fn key_ref(&self) -> &K;
+
+    /// Copy of the key, for building several commands from one key value.
+    fn cloned_key(&self) -> K
+        where K: Clone
+    {
+        self.key_ref().clone()
+    }
+}
+
+/// Key wrappers whose values the server stores as one known type.
+pub trait Typed {
+    /// The type the server stores values of this key as.
+    const REDIS_TYPE: RedisType;
 }
 
 pub fn mget<K, V, I>(keys: I) -> Cmd
@@ -25,166 +106,3765 @@ where K: ToRedisArgs,
     cmd.clone()
 }
 
-/// Commands from https://redis.io/commands/?group=generic
-pub trait GenericValue<K: ToRedisArgs>: Key<K> {
-    /// Get the expiration time of a key.
-    fn ttl(self) -> Cmd
-        where Self: Sized
-    {
-        Cmd::ttl(self.key())
+/// `MGET` all `keys`, returning each key with its value, `None` for the
+/// missing ones.
+pub fn mget_map<K, S, V, I>(keys: I, con: &mut dyn ConnectionLike)
+    -> RedisResult<HashMap<K, Option<V>>>
+where K: ToRedisArgs + Eq + Hash + Clone,
+      S: SingleValue<K>,
+      V: FromRedisValue,
+      I: IntoIterator<Item=S>,
+{
+    let keys: Vec<K> = keys.into_iter().map(|k| k.key()).collect();
+    if keys.is_empty() {
+        return Ok(HashMap::new());
     }
+    let values: Vec<Option<V>> = redis::cmd("MGET").arg(&keys).query(con)?;
+    Ok(keys.into_iter().zip(values).collect())
+}
 
-    /// Get the expiration time of a key in milliseconds.
-    fn pttl(self) -> Cmd
-        where Self: Sized
-    {
-        Cmd::pttl(self.key())
+/// Set the TTL of all `keys` in one pipeline, returning how many of them
+/// existed and had it applied.
+pub fn expire_many<K, V, I>(keys: I, ttl_secs: usize,
+                            con: &mut dyn ConnectionLike) -> RedisResult<usize>
+where K: ToRedisArgs,
+      V: GenericValue<K>,
+      I: IntoIterator<Item=V>,
+{
+    let mut pipe = redis::pipe();
+    for k in keys {
+        pipe.add_command(k.expire(ttl_secs));
     }
-
-    fn expire(self, ttl_secs: usize) -> Cmd 
-        where Self: Sized
-    {
-        Cmd::expire(self.key(), ttl_secs)
+    if pipe.cmd_iter().next().is_none() {
+        return Ok(0);
     }
+    let applied: Vec<bool> = pipe.query(con)?;
+    Ok(applied.into_iter().filter(|&a| a).count())
 }
 
-/// Values that can be `SET`, `GET`, etc
-pub trait SingleValue<K: ToRedisArgs>: Key<K> {
-    fn get<M: ToRedisArgs>(self) -> Cmd
-        where Self: Sized
-    {
-        Cmd::get(self.key())
+/// A key `require_keys` didn't find.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingKey<K> {
+    pub key: K,
+    /// The type the key's schema stores it as.
+    pub redis_type: RedisType,
+}
+
+/// Check that all `keys` exist, e.g. config hashes a service needs on
+/// startup, with one pipeline of `EXISTS`. The inner result lists the
+/// missing keys in the order they were given.
+pub fn require_keys<K, V, I>(keys: I, con: &mut dyn ConnectionLike)
+    -> RedisResult<Result<(), Vec<MissingKey<K>>>>
+where K: ToRedisArgs,
+      V: GenericValue<K> + Typed,
+      I: IntoIterator<Item=V>,
+{
+    let keys: Vec<K> = keys.into_iter().map(|k| k.key()).collect();
+    if keys.is_empty() {
+        return Ok(Ok(()));
+    }
+    let mut pipe = redis::pipe();
+    for k in &keys {
+        pipe.cmd("EXISTS").arg(k);
+    }
+    let exists: Vec<bool> = pipe.query(con)?;
+    let missing: Vec<MissingKey<K>> = keys.into_iter()
+        .zip(exists)
+        .filter(|(_, exists)| !exists)
+        .map(|(key, _)| MissingKey { key, redis_type: V::REDIS_TYPE })
+        .collect();
+    if missing.is_empty() {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(missing))
     }
+}
 
-    fn set<V: ToRedisArgs>(self, val: V) -> Cmd
-        where Self: Sized
-    {
-        Cmd::set(self.key(), val)
+/// Count the keys matching `pattern` by type.
+///
+/// Walks the keyspace with `SCAN`, so the server isn't blocked, and looks
+/// up the types of each page of keys in one pipeline. Keys deleted while
+/// scanning are skipped, and like any `SCAN` keys may be counted twice if
+/// the keyspace changes meanwhile.
+pub fn type_census(pattern: &str, con: &mut dyn ConnectionLike)
+    -> RedisResult<HashMap<RedisType, usize>>
+{
+    let mut census = HashMap::new();
+    let mut cursor = 0u64;
+    loop {
+        let (next, keys): (u64, Vec<Vec<u8>>) = redis::cmd("SCAN")
+            .arg(cursor).arg("MATCH").arg(pattern).arg("COUNT").arg(1000)
+            .query(con)?;
+        if !keys.is_empty() {
+            let mut pipe = redis::pipe();
+            for k in &keys {
+                pipe.cmd("TYPE").arg(k);
+            }
+            let types: Vec<RedisType> = pipe.query(con)?;
+            for t in types.into_iter().filter(|t| *t != RedisType::None) {
+                *census.entry(t).or_insert(0) += 1;
+            }
+        }
+        if next == 0 {
+            return Ok(census);
+        }
+        cursor = next;
     }
 }
 
-pub struct SetKey<K: ToRedisArgs> {
-    key: K,
+/// Returns the arguments of a command, including the command name itself.
+///
+/// Useful for asserting what a schema builds without a running server.
+pub fn args(cmd: &Cmd) -> Vec<Vec<u8>> {
+    cmd.args_iter()
+        .map(|arg| match arg {
+            Arg::Simple(bytes) => bytes.to_vec(),
+            // scan commands start iterating from cursor 0
+            Arg::Cursor => b"0".to_vec(),
+        })
+        .collect()
 }
 
-impl<K: ToRedisArgs> Key<K> for SetKey<K> {
-    fn new(key: K) -> Self {
-        SetKey { key }
+/// Formats a command as a human-readable command line, e.g. `SADD myset1 x`.
+///
+/// Arguments that are empty or contain whitespace, quotes or non-printable
+/// bytes are double-quoted, with non-printable bytes hex-escaped as `\xNN`.
+pub fn render(cmd: &Cmd) -> String {
+    args(cmd).iter()
+        .map(|arg| render_arg(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn render_arg(bytes: &[u8]) -> String {
+    let text = std::str::from_utf8(bytes).ok();
+    if let Some(text) = text {
+        let plain = !text.is_empty() && text.chars().all(|c| {
+            !c.is_whitespace() && !c.is_control() && c != '"' && c != '\\'
+        });
+        if plain {
+            return text.to_string();
+        }
     }
 
-    fn key(self) -> K {
-        self.key
+    let mut out = String::from("\"");
+    match text {
+        Some(text) => {
+            for c in text.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c if c.is_control() => {
+                        let mut buf = [0; 4];
+                        for b in c.encode_utf8(&mut buf).bytes() {
+                            out.push_str(&format!("\\x{:02x}", b));
+                        }
+                    }
+                    c => out.push(c),
+                }
+            }
+        }
+        None => {
+            for &b in bytes {
+                match b {
+                    b'"' => out.push_str("\\\""),
+                    b'\\' => out.push_str("\\\\"),
+                    b' ' => out.push(' '),
+                    b if b.is_ascii_graphic() => out.push(b as char),
+                    b => out.push_str(&format!("\\x{:02x}", b)),
+                }
+            }
+        }
     }
+    out.push('"');
+    out
 }
 
-impl<K: ToRedisArgs> GenericValue<K> for SetKey<K> {}
+thread_local! {
+    static RECORDED_KEYS: RefCell<Option<BTreeSet<String>>> =
+        const { RefCell::new(None) };
+    static RECORDER_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
 
-impl<K: ToRedisArgs> SetKey<K> {
-    pub fn sadd<M: ToRedisArgs>(self, member: M) -> Cmd {
-        Cmd::sadd(self.key, member)
+/// Collects every key wrapper constructed on the current thread while it's
+/// alive, for asserting which keys a code path touches.
+///
+/// Keys are stored as they'd appear in `render`. Starting a recorder while
+/// another one is active suspends the outer one until the inner is dropped.
+/// Nested recorders must be dropped innermost first, which debug builds
+/// assert. Recorders are tied to their thread, so they aren't `Send`.
+pub struct Recorder {
+    outer: Option<BTreeSet<String>>,
+    depth: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Recorder {
+    pub fn start() -> Recorder {
+        let outer = RECORDED_KEYS.with(|r| r.replace(Some(BTreeSet::new())));
+        let depth = RECORDER_DEPTH.with(|d| {
+            d.set(d.get() + 1);
+            d.get()
+        });
+        Recorder { outer, depth, _not_send: PhantomData }
     }
 
-    pub fn srem<M: ToRedisArgs>(self, member: M) -> Cmd {
-        Cmd::srem(self.key, member)
+    /// The keys recorded so far.
+    pub fn keys(&self) -> BTreeSet<String> {
+        RECORDED_KEYS.with(|r| r.borrow().clone().unwrap_or_default())
     }
+}
 
-    pub fn smembers(self) -> Cmd {
-        Cmd::smembers(self.key)
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let depth = RECORDER_DEPTH.with(|d| d.replace(self.depth - 1));
+        debug_assert!(thread::panicking() || self.depth == depth,
+                      "nested Recorder dropped out of order");
+        RECORDED_KEYS.with(|r| r.replace(self.outer.take()));
     }
+}
 
-    pub fn sunion<I: Iterator<Item=SetKey<K>>>(keys: I) -> Cmd {
-        let cmd = &mut Cmd::new();
-        cmd.arg("SUNION");
-        for k in keys {
-            cmd.arg(k.key());
+fn record_key<K: ToRedisArgs>(key: &K) {
+    RECORDED_KEYS.with(|r| {
+        if let Some(keys) = r.borrow_mut().as_mut() {
+            let rendered = key.to_redis_args().iter()
+                .map(|arg| render_arg(arg))
+                .collect::<Vec<_>>()
+                .join(" ");
+            keys.insert(rendered);
         }
-        cmd.clone()
+    });
+}
+
+/// Number of keys in the current database.
+pub fn dbsize() -> Cmd {
+    redis::cmd("DBSIZE")
+}
+
+/// **Deletes every key in the current database.**
+///
+/// With `async_mode` keys are freed in the background (`ASYNC`), otherwise
+/// the server blocks until they're gone (`SYNC`).
+#[cfg(feature = "test-helpers")]
+pub fn flushdb(async_mode: bool) -> Cmd {
+    let mut cmd = redis::cmd("FLUSHDB");
+    cmd.arg(if async_mode { "ASYNC" } else { "SYNC" });
+    cmd
+}
+
+/// **Deletes every key in every database of the server.**
+///
+/// With `async_mode` keys are freed in the background (`ASYNC`), otherwise
+/// the server blocks until they're gone (`SYNC`).
+#[cfg(feature = "test-helpers")]
+pub fn flushall(async_mode: bool) -> Cmd {
+    let mut cmd = redis::cmd("FLUSHALL");
+    cmd.arg(if async_mode { "ASYNC" } else { "SYNC" });
+    cmd
+}
+
+/// Run `INFO`, for just one `section` if given, and parse the reply with
+/// `parse_info`.
+pub fn info(section: Option<&str>, con: &mut dyn ConnectionLike)
+    -> RedisResult<HashMap<String, String>>
+{
+    let mut cmd = redis::cmd("INFO");
+    if let Some(section) = section {
+        cmd.arg(section);
     }
+    let reply: String = cmd.query(con)?;
+    Ok(parse_info(&reply))
+}
 
-    pub fn sinter<I: Iterator<Item=SetKey<K>>>(keys: I) -> Cmd {
-        let cmd = &mut Cmd::new();
-        cmd.arg("SINTER");
-        for k in keys {
-            cmd.arg(k.key());
+/// Parse the `name:value` lines of an `INFO` reply into a map, skipping
+/// blank lines and `# Section` headers. Values are split at the first
+/// colon, so the ones containing colons, like `executable`, stay intact.
+pub fn parse_info(reply: &str) -> HashMap<String, String> {
+    reply.lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Resets the connection state, like reconnecting without closing the socket.
+///
+/// Discards a pending `MULTI`, unwatches all `WATCH`ed keys, leaves all
+/// subscribed channels and patterns, selects database 0 and deauthenticates.
+pub fn reset() -> Cmd {
+    redis::cmd("RESET")
+}
+
+/// `CLIENT` commands from https://redis.io/commands/?group=connection
+pub mod client {
+    use redis::{Cmd, ToRedisArgs};
+
+    /// Assign a name to the current connection.
+    pub fn client_setname<N: ToRedisArgs>(name: N) -> Cmd {
+        let mut cmd = redis::cmd("CLIENT");
+        cmd.arg("SETNAME").arg(name);
+        cmd
+    }
+
+    /// Get the name of the current connection, nil if none was set.
+    pub fn client_getname() -> Cmd {
+        let mut cmd = redis::cmd("CLIENT");
+        cmd.arg("GETNAME");
+        cmd
+    }
+
+    /// Exclude the current connection from client eviction (Redis 7.0).
+    pub fn client_no_evict(on: bool) -> Cmd {
+        let mut cmd = redis::cmd("CLIENT");
+        cmd.arg("NO-EVICT").arg(if on { "ON" } else { "OFF" });
+        cmd
+    }
+}
+
+/// How often and how patiently `with_retry` retries.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: usize,
+    /// Delay before the first retry, doubled for each further one.
+    pub backoff: Duration,
+}
+
+/// Whether an error may go away on a later attempt, like a dropped
+/// connection or a server still loading its dataset. Errors in the command
+/// itself, such as `WRONGTYPE` or syntax errors, are not transient.
+///
+/// IO errors, timeouts and dropped connections included, only go away on
+/// a new connection, see `with_retry`.
+pub fn is_transient(err: &RedisError) -> bool {
+    matches!(err.kind(),
+             ErrorKind::IoError
+             | ErrorKind::BusyLoadingError
+             | ErrorKind::TryAgain
+             | ErrorKind::ClusterDown
+             | ErrorKind::MasterDown)
+}
+
+/// Run `f`, retrying it according to `policy` while it fails with a
+/// transient error.
+///
+/// `f` must check out a new connection on every attempt, e.g. with
+/// `Client::get_connection` or `SchemaClient::with_conn`. A `Connection`
+/// is never reconnected, so once dropped every retry on it fails the same
+/// way, and after a timeout it may still deliver the late reply to the
+/// previous attempt.
+///
+/// Commands are run at least once: a command that timed out or lost its
+/// connection may have been executed anyway, so retrying non-idempotent
+/// ones like `incr` or `rpush` can apply them twice.
+pub fn with_retry<T, F>(policy: RetryPolicy, mut f: F) -> RedisResult<T>
+where F: FnMut() -> RedisResult<T>,
+{
+    let mut delay = policy.backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if attempt < policy.max_attempts && is_transient(&e) => {
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
         }
-        cmd.clone()
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct StringKey<K: ToRedisArgs> {
-    key: K
+/// Receives the name and duration of every command sent through an
+/// `Instrumented` connection, e.g. to feed a metrics backend.
+pub trait Instrumentation {
+    /// `command` is the upper case command name such as `GET`, or
+    /// `PIPELINE` for pipelines and transactions.
+    fn observe(&self, command: &str, elapsed: Duration);
 }
 
-impl<K: ToRedisArgs> SingleValue<K> for StringKey<K> {
+impl<F: Fn(&str, Duration)> Instrumentation for F {
+    fn observe(&self, command: &str, elapsed: Duration) {
+        self(command, elapsed)
+    }
 }
 
-impl<K: ToRedisArgs> GenericValue<K> for StringKey<K> {}
+/// A connection that reports every command it sends to an `Instrumentation`.
+pub struct Instrumented<'a> {
+    con: &'a mut dyn ConnectionLike,
+    hook: &'a dyn Instrumentation,
+}
 
-impl<K: ToRedisArgs> Key<K> for StringKey<K> {
-    fn new(key: K) -> StringKey<K> {
-        StringKey { key }
+impl<'a> Instrumented<'a> {
+    pub fn new(con: &'a mut dyn ConnectionLike, hook: &'a dyn Instrumentation)
+        -> Instrumented<'a>
+    {
+        Instrumented { con, hook }
     }
+}
 
-    fn key(self) -> K {
-        self.key
+impl ConnectionLike for Instrumented<'_> {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        let started = Instant::now();
+        let result = self.con.req_packed_command(cmd);
+        self.hook.observe(&packed_command_name(cmd), started.elapsed());
+        result
+    }
+
+    fn req_packed_commands(&mut self, cmd: &[u8], offset: usize, count: usize)
+        -> RedisResult<Vec<Value>>
+    {
+        let started = Instant::now();
+        let result = self.con.req_packed_commands(cmd, offset, count);
+        self.hook.observe("PIPELINE", started.elapsed());
+        result
+    }
+
+    fn get_db(&self) -> i64 {
+        self.con.get_db()
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.con.check_connection()
+    }
+
+    fn is_open(&self) -> bool {
+        self.con.is_open()
     }
 }
 
-impl<K: ToRedisArgs> StringKey<K> {
+/// The name of a command packed as `*<argc>\r\n$<len>\r\n<name>\r\n...`.
+fn packed_command_name(packed: &[u8]) -> String {
+    let mut lines = packed.split(|&b| b == b'\n');
+    let name = lines.nth(2)
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .unwrap_or_default();
+    String::from_utf8_lossy(name).to_ascii_uppercase()
 }
 
-pub struct IntKey<K: ToRedisArgs> {
-    key: K
+/// Runs schema commands on connections from an r2d2 pool, so callers don't
+/// have to check connections out themselves.
+#[cfg(feature = "pool")]
+#[derive(Clone)]
+pub struct SchemaClient {
+    pool: r2d2::Pool<redis::Client>,
+    instrumentation: Option<Arc<dyn Instrumentation + Send + Sync>>,
 }
 
-impl<K: ToRedisArgs> Key<K> for IntKey<K> {
-    fn new(key: K) -> IntKey<K> {
-        IntKey { key }
+#[cfg(feature = "pool")]
+impl SchemaClient {
+    pub fn new(pool: r2d2::Pool<redis::Client>) -> SchemaClient {
+        SchemaClient { pool, instrumentation: None }
     }
 
-    fn key(self) -> K {
-        self.key
+    /// Connect to `url` with a pool using r2d2's default settings.
+    pub fn open(url: &str) -> RedisResult<SchemaClient> {
+        let client = redis::Client::open(url)?;
+        let pool = r2d2::Pool::new(client).map_err(pool_error)?;
+        Ok(SchemaClient::new(pool))
+    }
+
+    /// Report every command run through this client to `hook`.
+    pub fn with_instrumentation<I>(mut self, hook: I) -> SchemaClient
+    where I: Instrumentation + Send + Sync + 'static,
+    {
+        self.instrumentation = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn pool(&self) -> &r2d2::Pool<redis::Client> {
+        &self.pool
+    }
+
+    /// Run `f` with a pooled connection, e.g. one of the typed helpers
+    /// like `|con| key.get_touch(60, con)`.
+    pub fn with_conn<T, F>(&self, f: F) -> RedisResult<T>
+    where F: FnOnce(&mut dyn ConnectionLike) -> RedisResult<T>,
+    {
+        let mut con = self.pool.get().map_err(pool_error)?;
+        match &self.instrumentation {
+            Some(hook) => f(&mut Instrumented::new(&mut *con, &**hook)),
+            None => f(&mut *con),
+        }
+    }
+
+    /// Run a command built by the schema on a pooled connection.
+    pub fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> RedisResult<T> {
+        self.with_conn(|con| cmd.query(con))
     }
 }
 
-impl<K: ToRedisArgs> SingleValue<K> for IntKey<K> {
+/// Failing to check out a connection is reported as an IO error, so
+/// `with_retry` treats it as transient.
+#[cfg(feature = "pool")]
+fn pool_error(e: r2d2::Error) -> RedisError {
+    RedisError::from((ErrorKind::IoError, "Connection pool error", e.to_string()))
 }
 
-impl<K: ToRedisArgs> IntKey<K> {
-    pub fn incr<A: ToRedisArgs>(self, amount: A) -> Cmd {
-        Cmd::incr(self.key, amount)
+const MOVE_ACROSS_DB_SCRIPT: &str = "\
+    if redis.call('COPY', KEYS[1], KEYS[2], 'DB', ARGV[1]) == 1 then
+        redis.call('DEL', KEYS[1])
+        return 1
+    end
+    return 0";
+
+/// Random numbers for `GenericValue::expire_jittered_with`, so tests can
+/// pick the TTL.
+pub trait RandomSource {
+    fn next_u64(&mut self) -> u64;
+}
+
+impl<F: FnMut() -> u64> RandomSource for F {
+    fn next_u64(&mut self) -> u64 {
+        self()
     }
 }
 
+/// `RandomSource` using the random keys std seeds `HashMap`s with. Good
+/// enough to spread TTLs, not for anything security sensitive.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdRandom;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl RandomSource for StdRandom {
+    fn next_u64(&mut self) -> u64 {
+        // every RandomState gets different keys, so even hashing nothing
+        // gives a new number each time
+        RandomState::new().build_hasher().finish()
+    }
+}
 
-    #[derive(Clone, Copy)]
-    struct Schema;
-    impl Schema {
-        pub fn myset1(self) -> SetKey<String> {
-            SetKey::new("myset1".to_string())
+/// A TTL in `[base_secs, base_secs + jitter_secs]`.
+fn jittered_ttl(base_secs: usize, jitter_secs: usize, rng: &mut dyn RandomSource) -> usize {
+    let jitter = match (jitter_secs as u64).checked_add(1) {
+        Some(range) => rng.next_u64() % range,
+        None => rng.next_u64(),
+    };
+    base_secs.saturating_add(jitter as usize)
+}
+
+const SWAP_SCRIPT: &str = "\
+    if KEYS[1] == KEYS[2] then
+        return
+    end
+    local first = redis.call('EXISTS', KEYS[1]) == 1
+    local second = redis.call('EXISTS', KEYS[2]) == 1
+    if first and second then
+        local value = redis.call('DUMP', KEYS[2])
+        local ttl = redis.call('PTTL', KEYS[2])
+        redis.call('RENAME', KEYS[1], KEYS[2])
+        redis.call('RESTORE', KEYS[1], math.max(ttl, 0), value)
+    elseif first then
+        redis.call('RENAME', KEYS[1], KEYS[2])
+    elseif second then
+        redis.call('RENAME', KEYS[2], KEYS[1])
+    end";
+
+/// Commands from https://redis.io/commands/?group=generic
+pub trait GenericValue<K: ToRedisArgs>: Key<K> {
+    /// Get the expiration time of a key.
+    fn ttl(self) -> Cmd
+        where Self: Sized
+    {
+        Cmd::ttl(self.key())
+    }
+
+    /// Get the expiration time of a key in milliseconds.
+    fn pttl(self) -> Cmd
+        where Self: Sized
+    {
+        Cmd::pttl(self.key())
+    }
+
+    fn expire(self, ttl_secs: usize) -> Cmd 
+        where Self: Sized
+    {
+        Cmd::expire(self.key(), ttl_secs)
+    }
+
+    /// `EXPIRE` only if `option`'s condition holds (Redis 7.0), replying
+    /// whether the TTL was set.
+    fn expire_with(self, ttl_secs: usize, option: ExpireOption) -> Cmd
+        where Self: Sized
+    {
+        let mut cmd = redis::cmd("EXPIRE");
+        cmd.arg(self.key()).arg(ttl_secs).arg(option);
+        cmd
+    }
+
+    /// Get the unix time in seconds the key expires at (Redis 7.0), -1 if
+    /// it has no expiry and -2 if it's missing.
+    fn expiretime(self) -> Cmd
+        where Self: Sized
+    {
+        let mut cmd = redis::cmd("EXPIRETIME");
+        cmd.arg(self.key());
+        cmd
+    }
+
+    /// Remove the expiration from a key.
+    fn persist(self) -> Cmd
+        where Self: Sized
+    {
+        Cmd::persist(self.key())
+    }
+
+    /// Set the key's TTL if the key exists, returning whether it did.
+    ///
+    /// Plain `EXPIRE` never creates a key, so a deleted key is not
+    /// resurrected. `EXPIRE ... XX` isn't used as it would also skip
+    /// existing keys that have no TTL yet.
+    fn refresh_ttl(self, ttl_secs: usize, con: &mut dyn ConnectionLike)
+        -> RedisResult<bool>
+        where Self: Sized
+    {
+        self.expire(ttl_secs).query(con)
+    }
+
+    /// `PERSIST` returning whether a TTL was removed.
+    fn persist_ok(self, con: &mut dyn ConnectionLike) -> RedisResult<bool>
+        where Self: Sized
+    {
+        self.persist().query(con)
+    }
+
+    /// `EXPIRE` with a random TTL from `base_secs` to `base_secs +
+    /// jitter_secs`, so keys cached together don't all expire at once.
+    /// Returns whether the key exists and had the TTL set.
+    fn expire_jittered(self, base_secs: usize, jitter_secs: usize,
+                       con: &mut dyn ConnectionLike) -> RedisResult<bool>
+        where Self: Sized
+    {
+        self.expire_jittered_with(base_secs, jitter_secs, &mut StdRandom, con)
+    }
+
+    /// `expire_jittered` taking random numbers from `rng`.
+    fn expire_jittered_with(self, base_secs: usize, jitter_secs: usize,
+                            rng: &mut dyn RandomSource,
+                            con: &mut dyn ConnectionLike) -> RedisResult<bool>
+        where Self: Sized
+    {
+        self.expire(jittered_ttl(base_secs, jitter_secs, rng)).query(con)
+    }
+
+    /// `COPY` the key to `dest` in database `dest_db` (Redis 6.2).
+    ///
+    /// Replies 1 if copied, 0 if the source is missing or `dest` exists.
+    fn copy_to_db(self, dest: Self, dest_db: usize) -> Cmd
+        where Self: Sized
+    {
+        let mut cmd = redis::cmd("COPY");
+        cmd.arg(self.key()).arg(dest.key()).arg("DB").arg(dest_db);
+        cmd
+    }
+
+    /// Move the key to `dest` in database `dest_db` with `COPY` and `DEL`.
+    ///
+    /// Returns false, leaving both databases untouched, when the source is
+    /// missing or `dest` already exists. The two commands aren't atomic: a
+    /// failed `DEL` leaves the value in both databases and other clients
+    /// can see both copies in between. Use `move_across_db_atomic` when
+    /// that matters.
+    fn move_across_db(self, dest: Self, dest_db: usize,
+                      con: &mut dyn ConnectionLike) -> RedisResult<bool>
+        where Self: Sized, K: Clone
+    {
+        let src = self.key();
+        let copied: bool = Self::new(src.clone()).copy_to_db(dest, dest_db).query(con)?;
+        if copied {
+            Cmd::del(src).query::<()>(con)?;
         }
+        Ok(copied)
     }
 
-    #[test]
-    fn test_with_redis() -> Result<(), Box<dyn std::error::Error>> {
-        let client = redis::Client::open("redis://127.0.0.1/")?;
-        let mut con = client.get_connection()?;
+    /// Like `move_across_db`, but runs `COPY` and `DEL` in a Lua script so
+    /// no other client sees an intermediate state.
+    fn move_across_db_atomic(self, dest: Self, dest_db: usize,
+                             con: &mut dyn ConnectionLike) -> RedisResult<bool>
+        where Self: Sized
+    {
+        redis::cmd("EVAL")
+            .arg(MOVE_ACROSS_DB_SCRIPT)
+            .arg(2).arg(self.key()).arg(dest.key())
+            .arg(dest_db)
+            .query(con)
+    }
 
-        let s = Schema;
-        s.myset1().del().query(&mut con)?;
-        s.myset1().sadd("first").query(&mut con)?;
-        let members: Vec<String> =
-            s.myset1().smembers().query(&mut con)?;
+    /// Exchange the values of this key and `other`, TTLs included.
+    ///
+    /// Runs in a Lua script, so it's atomic: no other client sees either
+    /// key with the other's value or missing halfway through. If only one
+    /// of the keys exists, its value moves to the other key and it's left
+    /// missing.
+    fn swap_with(self, other: Self, con: &mut dyn ConnectionLike) -> RedisResult<()>
+        where Self: Sized
+    {
+        redis::cmd("EVAL")
+            .arg(SWAP_SCRIPT)
+            .arg(2).arg(self.key()).arg(other.key())
+            .query(con)
+    }
 
-        assert_eq!(vec!["first"], members);
+    /// Serialize the key's value, nil if the key is missing.
+    fn dump(self) -> Cmd
+        where Self: Sized
+    {
+        let mut cmd = redis::cmd("DUMP");
+        cmd.arg(self.key());
+        cmd
+    }
 
-        Cmd::sadd(&["test_first", "test_second"], "member")
+    /// Create the key from a `dump` payload, expiring in `ttl_ms`
+    /// milliseconds or never if 0. Fails with `BUSYKEY` if the key exists,
+    /// unless `replace` is set.
+    fn restore(self, ttl_ms: usize, data: &[u8], replace: bool) -> Cmd
+        where Self: Sized
+    {
+        let mut cmd = redis::cmd("RESTORE");
+        cmd.arg(self.key()).arg(ttl_ms).arg(data);
+        if replace {
+            cmd.arg("REPLACE");
+        }
+        cmd
+    }
+
+    /// Low level details about the key's value from `DEBUG OBJECT`.
+    #[cfg(feature = "debug-commands")]
+    fn debug_object(self, con: &mut dyn ConnectionLike) -> RedisResult<DebugObject>
+        where Self: Sized
+    {
+        redis::cmd("DEBUG").arg("OBJECT").arg(self.key()).query(con)
+    }
+
+    /// Get the internal encoding of a key's value, nil if the key is missing.
+    fn object_encoding(self) -> Cmd
+        where Self: Sized
+    {
+        let mut cmd = redis::cmd("OBJECT");
+        cmd.arg("ENCODING").arg(self.key());
+        cmd
+    }
+
+    /// Fail with a `TypeError` unless the key exists and its value is
+    /// stored with the `expected` encoding. Meant for tests that check a
+    /// schema keeps values in a compact encoding.
+    fn assert_encoding(self, expected: Encoding, con: &mut dyn ConnectionLike)
+        -> RedisResult<()>
+        where Self: Sized
+    {
+        let actual: Option<Encoding> = self.object_encoding().query(con)?;
+        match actual {
+            Some(actual) if actual == expected => Ok(()),
+            Some(actual) => Err(RedisError::from((
+                ErrorKind::TypeError,
+                "Unexpected encoding",
+                format!("expected {}, found {}", expected.name(), actual.name()),
+            ))),
+            None => Err(RedisError::from((
+                ErrorKind::TypeError,
+                "Unexpected encoding",
+                format!("expected {}, but the key doesn't exist", expected.name()),
+            ))),
+        }
+    }
+}
+
+/// Reply of `DEBUG OBJECT`.
+///
+/// Which fields are reported depends on the server version and the value's
+/// type, e.g. `ql_nodes` is only there for lists.
+#[cfg(feature = "debug-commands")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DebugObject {
+    pub refcount: Option<i64>,
+    pub encoding: Option<String>,
+    pub serialized_length: Option<usize>,
+    pub lru_seconds_idle: Option<u64>,
+    pub ql_nodes: Option<usize>,
+    /// Every `name:value` field of the reply, including the ones above.
+    pub fields: HashMap<String, String>,
+}
+
+#[cfg(feature = "debug-commands")]
+impl DebugObject {
+    pub fn parse(reply: &str) -> DebugObject {
+        let fields: HashMap<String, String> = reply.split_whitespace()
+            .filter_map(|field| field.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        DebugObject {
+            refcount: parse_field(&fields, "refcount"),
+            encoding: fields.get("encoding").cloned(),
+            serialized_length: parse_field(&fields, "serializedlength"),
+            lru_seconds_idle: parse_field(&fields, "lru_seconds_idle"),
+            ql_nodes: parse_field(&fields, "ql_nodes"),
+            fields,
+        }
+    }
+}
+
+#[cfg(feature = "debug-commands")]
+fn parse_field<T: std::str::FromStr>(fields: &HashMap<String, String>,
+                                     name: &str) -> Option<T> {
+    fields.get(name).and_then(|v| v.parse().ok())
+}
+
+#[cfg(feature = "debug-commands")]
+impl FromRedisValue for DebugObject {
+    fn from_redis_value(v: &Value) -> RedisResult<DebugObject> {
+        Ok(DebugObject::parse(&String::from_redis_value(v)?))
+    }
+}
+
+/// Internal encodings as reported by `OBJECT ENCODING`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Raw,
+    Int,
+    EmbStr,
+    ListPack,
+    ZipList,
+    QuickList,
+    IntSet,
+    HashTable,
+    SkipList,
+    Stream,
+    Other(String),
+}
+
+impl Encoding {
+    pub fn name(&self) -> &str {
+        match self {
+            Encoding::Raw => "raw",
+            Encoding::Int => "int",
+            Encoding::EmbStr => "embstr",
+            Encoding::ListPack => "listpack",
+            Encoding::ZipList => "ziplist",
+            Encoding::QuickList => "quicklist",
+            Encoding::IntSet => "intset",
+            Encoding::HashTable => "hashtable",
+            Encoding::SkipList => "skiplist",
+            Encoding::Stream => "stream",
+            Encoding::Other(name) => name,
+        }
+    }
+}
+
+impl FromRedisValue for Encoding {
+    fn from_redis_value(v: &Value) -> RedisResult<Encoding> {
+        let name = String::from_redis_value(v)?;
+        Ok(match name.as_str() {
+            "raw" => Encoding::Raw,
+            "int" => Encoding::Int,
+            "embstr" => Encoding::EmbStr,
+            "listpack" => Encoding::ListPack,
+            "ziplist" => Encoding::ZipList,
+            "quicklist" => Encoding::QuickList,
+            "intset" => Encoding::IntSet,
+            "hashtable" => Encoding::HashTable,
+            "skiplist" => Encoding::SkipList,
+            "stream" => Encoding::Stream,
+            _ => Encoding::Other(name),
+        })
+    }
+}
+
+/// What a key holds, from its type and encoding. Strings are split by
+/// encoding, so integer counters can be told apart from text.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum KeyKind {
+    /// A string stored as an integer, as `IntKey` values are.
+    Int,
+    /// A short string, embedded in its object header.
+    EmbStr,
+    RawString,
+    List,
+    Set,
+    ZSet,
+    Hash,
+    Stream,
+    /// Types added by modules.
+    Other(String),
+}
+
+impl KeyKind {
+    /// Classify a value by its `TYPE` and `OBJECT ENCODING`, `None` if the
+    /// key is missing.
+    pub fn classify(redis_type: RedisType, encoding: Option<Encoding>) -> Option<KeyKind> {
+        Some(match redis_type {
+            RedisType::None => return None,
+            RedisType::String => match encoding {
+                Some(Encoding::Int) => KeyKind::Int,
+                Some(Encoding::EmbStr) => KeyKind::EmbStr,
+                _ => KeyKind::RawString,
+            },
+            RedisType::List => KeyKind::List,
+            RedisType::Set => KeyKind::Set,
+            RedisType::ZSet => KeyKind::ZSet,
+            RedisType::Hash => KeyKind::Hash,
+            RedisType::Stream => KeyKind::Stream,
+            RedisType::Other(name) => KeyKind::Other(name),
+        })
+    }
+}
+
+/// Find out what `key` holds, for tooling that reads keys without knowing
+/// their schema. Runs `TYPE` and `OBJECT ENCODING` in one pipeline and
+/// returns `None` if the key is missing.
+pub fn inspect<K: ToRedisArgs>(key: K, con: &mut dyn ConnectionLike)
+    -> RedisResult<Option<KeyKind>>
+{
+    let (redis_type, encoding): (RedisType, Option<Encoding>) = redis::pipe()
+        .cmd("TYPE").arg(&key)
+        .cmd("OBJECT").arg("ENCODING").arg(&key)
+        .query(con)?;
+    Ok(KeyKind::classify(redis_type, encoding))
+}
+
+/// Result of `migrate_key`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrateOutcome {
+    Migrated,
+    SourceMissing,
+    /// The key exists on the destination and `replace` wasn't set.
+    DestinationExisted,
+}
+
+/// Copy a key to another server or database with `DUMP` and `RESTORE`.
+///
+/// The destination expires in `ttl_ms` milliseconds if given, where
+/// `Some(0)` means it never expires like with `RESTORE`, otherwise the
+/// source's remaining TTL is kept. An existing destination key is
+/// overwritten only if `replace` is set. With `delete_source` the source
+/// key is deleted once the destination is written.
+pub fn migrate_key<K, V>(src: &mut dyn ConnectionLike,
+                         dst: &mut dyn ConnectionLike,
+                         key: V, ttl_ms: Option<usize>,
+                         replace: bool, delete_source: bool)
+    -> RedisResult<MigrateOutcome>
+where K: ToRedisArgs + Clone,
+      V: GenericValue<K>,
+{
+    // atomic so the key can't expire between DUMP and PTTL
+    let key = key.key();
+    let (data, pttl): (Option<Vec<u8>>, i64) = redis::pipe()
+        .atomic()
+        .add_command(V::new(key.clone()).dump())
+        .add_command(V::new(key.clone()).pttl())
+        .query(src)?;
+    let data = match data {
+        Some(data) if pttl != -2 => data,
+        _ => return Ok(MigrateOutcome::SourceMissing),
+    };
+    // PTTL is -1 for keys without an expiry, which RESTORE takes as 0
+    let ttl_ms = ttl_ms.unwrap_or_else(|| pttl.max(0) as usize);
+
+    let restored = V::new(key.clone())
+        .restore(ttl_ms, &data, replace)
+        .query::<()>(dst);
+    match restored {
+        Err(e) if e.code() == Some("BUSYKEY") => {
+            return Ok(MigrateOutcome::DestinationExisted)
+        }
+        other => other?,
+    }
+
+    if delete_source {
+        V::new(key).del().query::<()>(src)?;
+    }
+    Ok(MigrateOutcome::Migrated)
+}
+
+/// Errors detected by the schema before anything is sent to Redis.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The value serializes to `size` bytes, more than the allowed `max`.
+    ValueTooLarge { size: usize, max: usize },
+    /// Options that can't be used together, e.g. `EX` and `KEEPTTL`.
+    ConflictingOptions(Vec<&'static str>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ValueTooLarge { size, max } => {
+                write!(f, "value of {} bytes exceeds the limit of {} bytes", size, max)
+            }
+            Error::ConflictingOptions(options) => {
+                write!(f, "options can't be used together: {}", options.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn check_value_size<V: ToRedisArgs>(val: &V, max_value_bytes: usize)
+    -> Result<(), Error>
+{
+    let size = val.to_redis_args().iter().map(|arg| arg.len()).sum();
+    if size > max_value_bytes {
+        return Err(Error::ValueTooLarge { size, max: max_value_bytes });
+    }
+    Ok(())
+}
+
+/// `SET` with options, from `SingleValue::set_with`.
+///
+/// At most one of `ex`, `px`, `exat`, `pxat` and `keep_ttl` may be used.
+pub struct SetBuilder {
+    cmd: Cmd,
+    expiry: Vec<(&'static str, Option<u64>)>,
+}
+
+impl SetBuilder {
+    fn new(cmd: Cmd) -> SetBuilder {
+        SetBuilder { cmd, expiry: Vec::new() }
+    }
+
+    /// Expire in `secs` seconds.
+    pub fn ex(mut self, secs: u64) -> SetBuilder {
+        self.expiry.push(("EX", Some(secs)));
+        self
+    }
+
+    /// Expire in `millis` milliseconds.
+    pub fn px(mut self, millis: u64) -> SetBuilder {
+        self.expiry.push(("PX", Some(millis)));
+        self
+    }
+
+    /// Expire at the unix time `unix_secs` (Redis 6.2).
+    pub fn exat(mut self, unix_secs: u64) -> SetBuilder {
+        self.expiry.push(("EXAT", Some(unix_secs)));
+        self
+    }
+
+    /// Expire at the unix time `unix_millis` in milliseconds (Redis 6.2).
+    pub fn pxat(mut self, unix_millis: u64) -> SetBuilder {
+        self.expiry.push(("PXAT", Some(unix_millis)));
+        self
+    }
+
+    /// Keep the key's current TTL instead of clearing it (Redis 6.0).
+    pub fn keep_ttl(mut self) -> SetBuilder {
+        self.expiry.push(("KEEPTTL", None));
+        self
+    }
+
+    /// The `SET` command, or `Error::ConflictingOptions` if more than one
+    /// expiry option was given.
+    pub fn build(self) -> Result<Cmd, Error> {
+        let mut cmd = self.cmd;
+        match self.expiry.as_slice() {
+            [] => {}
+            [(option, arg)] => {
+                cmd.arg(*option).arg(*arg);
+            }
+            options => {
+                let names = options.iter().map(|(name, _)| *name).collect();
+                return Err(Error::ConflictingOptions(names));
+            }
+        }
+        Ok(cmd)
+    }
+}
+
+/// Values that can be `SET`, `GET`, etc
+pub trait SingleValue<K: ToRedisArgs>: Key<K> {
+    fn get<M: ToRedisArgs>(self) -> Cmd
+        where Self: Sized
+    {
+        Cmd::get(self.key())
+    }
+
+    fn set<V: ToRedisArgs>(self, val: V) -> Cmd
+        where Self: Sized
+    {
+        Cmd::set(self.key(), val)
+    }
+
+    /// Start building a `SET` with options such as an expiry.
+    fn set_with<V: ToRedisArgs>(self, val: V) -> SetBuilder
+        where Self: Sized
+    {
+        SetBuilder::new(self.set(val))
+    }
+
+    /// `set`, unless `val` serializes to more than `max_value_bytes`.
+    fn checked_set<V: ToRedisArgs>(self, val: V, max_value_bytes: usize)
+        -> Result<Cmd, Error>
+        where Self: Sized
+    {
+        check_value_size(&val, max_value_bytes)?;
+        Ok(self.set(val))
+    }
+}
+
+/// Keys whose size can be measured without `MEMORY USAGE`.
+pub trait Cardinality<K: ToRedisArgs>: Key<K> {
+    /// Command replying the number of elements for containers or the
+    /// length in bytes for strings.
+    fn size(self) -> Cmd;
+
+    /// Number of elements for containers, length in bytes for strings,
+    /// 0 if the key is missing.
+    fn approx_size(self, con: &mut dyn ConnectionLike) -> RedisResult<usize>
+        where Self: Sized
+    {
+        self.size().query(con)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SetKey<K: ToRedisArgs> {
+    key: K,
+}
+
+impl<K: ToRedisArgs> Key<K> for SetKey<K> {
+    fn new(key: K) -> Self {
+        record_key(&key);
+        SetKey { key }
+    }
+
+    fn key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: ToRedisArgs> KeyRef<K> for SetKey<K> {
+    fn key_ref(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: ToRedisArgs> Typed for SetKey<K> {
+    const REDIS_TYPE: RedisType = RedisType::Set;
+}
+
+impl<K: ToRedisArgs> GenericValue<K> for SetKey<K> {}
+
+impl<K: ToRedisArgs> Cardinality<K> for SetKey<K> {
+    fn size(self) -> Cmd {
+        self.scard()
+    }
+}
+
+impl<K: ToRedisArgs> SetKey<K> {
+    pub fn sadd<M: ToRedisArgs>(self, member: M) -> Cmd {
+        self.sadd_many(std::iter::once(member))
+    }
+
+    /// Add several members in one `SADD`, replying how many were added.
+    ///
+    /// `members` must not be empty.
+    pub fn sadd_many<M, I>(self, members: I) -> Cmd
+    where M: ToRedisArgs,
+          I: IntoIterator<Item=M>,
+    {
+        let mut cmd = redis::cmd("SADD");
+        cmd.arg(self.key);
+        for m in members {
+            cmd.arg(m);
+        }
+        cmd
+    }
+
+    pub fn srem<M: ToRedisArgs>(self, member: M) -> Cmd {
+        self.srem_many(std::iter::once(member))
+    }
+
+    /// Remove several members in one `SREM`, replying how many were removed.
+    ///
+    /// `members` must not be empty.
+    pub fn srem_many<M, I>(self, members: I) -> Cmd
+    where M: ToRedisArgs,
+          I: IntoIterator<Item=M>,
+    {
+        let mut cmd = redis::cmd("SREM");
+        cmd.arg(self.key);
+        for m in members {
+            cmd.arg(m);
+        }
+        cmd
+    }
+
+    pub fn scard(self) -> Cmd {
+        Cmd::scard(self.key)
+    }
+
+    pub fn smembers(self) -> Cmd {
+        Cmd::smembers(self.key)
+    }
+
+    /// Up to `n` distinct random members, fewer if the set is smaller.
+    pub fn sample<V: FromRedisValue>(self, n: usize, con: &mut dyn ConnectionLike)
+        -> RedisResult<Vec<V>>
+    {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        redis::cmd("SRANDMEMBER").arg(self.key).arg(n).query(con)
+    }
+
+    /// `n` random members, possibly repeated; empty if the set is missing.
+    pub fn sample_with_replacement<V: FromRedisValue>(self, n: usize,
+                                                      con: &mut dyn ConnectionLike)
+        -> RedisResult<Vec<V>>
+    {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        redis::cmd("SRANDMEMBER").arg(self.key).arg(-(n as i64)).query(con)
+    }
+
+    /// Add all `members`, returning how many were newly added.
+    ///
+    /// Compact set encodings (`intset`, `listpack`) insert in linear time,
+    /// so a large variadic `SADD` into one can block the server for a
+    /// while. Unless `OBJECT ENCODING` reports a `hashtable`, batches of
+    /// more than `threshold` members are split into pipelined `SADD`s of
+    /// at most `threshold` members each; otherwise one `SADD` is sent.
+    pub fn add_all<M: ToRedisArgs>(self, members: &[M], threshold: usize,
+                                   con: &mut dyn ConnectionLike)
+        -> RedisResult<usize>
+        where K: Clone
+    {
+        if members.is_empty() {
+            return Ok(0);
+        }
+        if members.len() <= threshold {
+            return Cmd::sadd(self.key, members).query(con);
+        }
+        // the encoding only matters when the members would be split
+        let encoding: Option<Encoding> =
+            self.clone().object_encoding().query(con)?;
+        if encoding == Some(Encoding::HashTable) {
+            return Cmd::sadd(self.key, members).query(con);
+        }
+
+        let mut pipe = redis::pipe();
+        for chunk in members.chunks(threshold.max(1)) {
+            pipe.add_command(Cmd::sadd(self.key.clone(), chunk));
+        }
+        let added: Vec<usize> = pipe.query(con)?;
+        Ok(added.into_iter().sum())
+    }
+
+    /// Make the set contain exactly the `desired` members, returning how
+    /// many were `(added, removed)`.
+    ///
+    /// Reads the current members and sends only the difference, as one
+    /// `SADD` and one `SREM` in a transaction. Changes other clients make
+    /// between the read and the write may be overwritten. The counts come
+    /// from the server's replies, so members another client already added
+    /// or removed in between aren't counted.
+    pub fn sync_to<M, I>(self, desired: I, con: &mut dyn ConnectionLike)
+        -> RedisResult<(usize, usize)>
+    where M: Eq + Hash + ToRedisArgs + FromRedisValue,
+          I: IntoIterator<Item=M>,
+          K: Clone,
+    {
+        let desired: HashSet<M> = desired.into_iter().collect();
+        let current: HashSet<M> = self.clone().smembers().query(con)?;
+        let to_add: Vec<&M> = desired.difference(&current).collect();
+        let to_remove: Vec<&M> = current.difference(&desired).collect();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if !to_add.is_empty() {
+            pipe.add_command(self.clone().sadd_many(&to_add));
+        }
+        if !to_remove.is_empty() {
+            pipe.add_command(self.srem_many(&to_remove));
+        }
+        if to_add.is_empty() && to_remove.is_empty() {
+            return Ok((0, 0));
+        }
+        let mut replies = pipe.query::<Vec<usize>>(con)?.into_iter();
+        let added = if to_add.is_empty() { 0 } else { replies.next().unwrap_or(0) };
+        let removed = if to_remove.is_empty() { 0 } else { replies.next().unwrap_or(0) };
+        Ok((added, removed))
+    }
+
+    /// Check membership of several members at once (Redis 6.2).
+    ///
+    /// The reply is a `Vec<bool>` in the same order as `members`.
+    pub fn smismember<M: ToRedisArgs>(self, members: M) -> Cmd {
+        let mut cmd = redis::cmd("SMISMEMBER");
+        cmd.arg(self.key).arg(members);
+        cmd
+    }
+
+    /// Like `smismember`, but pairs every member with its reply so results
+    /// can't be matched to the wrong member.
+    pub fn sismember_map<M>(self, members: Vec<M>, con: &mut dyn ConnectionLike)
+        -> RedisResult<HashMap<M, bool>>
+    where M: Eq + Hash + Clone + ToRedisArgs
+    {
+        if members.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let found: Vec<bool> = self.smismember(&members).query(con)?;
+        Ok(members.into_iter().zip(found).collect())
+    }
+
+    pub fn sunion<I: Iterator<Item=SetKey<K>>>(keys: I) -> Cmd {
+        let cmd = &mut Cmd::new();
+        cmd.arg("SUNION");
+        for k in keys {
+            cmd.arg(k.key());
+        }
+        cmd.clone()
+    }
+
+    pub fn sinter<I: Iterator<Item=SetKey<K>>>(keys: I) -> Cmd {
+        let cmd = &mut Cmd::new();
+        cmd.arg("SINTER");
+        for k in keys {
+            cmd.arg(k.key());
+        }
+        cmd.clone()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StringKey<K: ToRedisArgs> {
+    key: K
+}
+
+impl<K: ToRedisArgs> SingleValue<K> for StringKey<K> {
+}
+
+impl<K: ToRedisArgs> GenericValue<K> for StringKey<K> {}
+
+impl<K: ToRedisArgs> Cardinality<K> for StringKey<K> {
+    fn size(self) -> Cmd {
+        Cmd::strlen(self.key)
+    }
+}
+
+impl<K: ToRedisArgs> Key<K> for StringKey<K> {
+    fn new(key: K) -> StringKey<K> {
+        record_key(&key);
+        StringKey { key }
+    }
+
+    fn key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: ToRedisArgs> KeyRef<K> for StringKey<K> {
+    fn key_ref(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: ToRedisArgs> Typed for StringKey<K> {
+    const REDIS_TYPE: RedisType = RedisType::String;
+}
+
+impl<K: ToRedisArgs> StringKey<K> {
+    /// Get the value and set its TTL to `ttl_secs` (Redis 6.2).
+    pub fn getex(self, ttl_secs: usize) -> Cmd {
+        let mut cmd = redis::cmd("GETEX");
+        cmd.arg(self.key).arg("EX").arg(ttl_secs);
+        cmd
+    }
+
+    /// Read the value and restart its TTL in one round trip, for sliding
+    /// expiration caches. Returns `None` if the key is missing.
+    pub fn get_touch<V: FromRedisValue>(self, ttl_secs: usize,
+                                        con: &mut dyn ConnectionLike)
+        -> RedisResult<Option<V>>
+    {
+        self.getex(ttl_secs).query(con)
+    }
+}
+
+pub struct IntKey<K: ToRedisArgs> {
+    key: K
+}
+
+impl<K: ToRedisArgs> Key<K> for IntKey<K> {
+    fn new(key: K) -> IntKey<K> {
+        record_key(&key);
+        IntKey { key }
+    }
+
+    fn key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: ToRedisArgs> KeyRef<K> for IntKey<K> {
+    fn key_ref(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: ToRedisArgs> Typed for IntKey<K> {
+    const REDIS_TYPE: RedisType = RedisType::String;
+}
+
+impl<K: ToRedisArgs> SingleValue<K> for IntKey<K> {
+}
+
+impl<K: ToRedisArgs> Cardinality<K> for IntKey<K> {
+    fn size(self) -> Cmd {
+        Cmd::strlen(self.key)
+    }
+}
+
+impl<K: ToRedisArgs> IntKey<K> {
+    pub fn incr<A: ToRedisArgs>(self, amount: A) -> Cmd {
+        Cmd::incr(self.key, amount)
+    }
+
+    /// Atomically set the counter to 0 and return its previous value,
+    /// treating a missing key as 0.
+    ///
+    /// Uses `SET key 0 GET`, which needs Redis 6.2. Older servers can run
+    /// `Cmd::getset(key, 0)` instead, which has the same effect.
+    ///
+    /// If the key held something other than an integer, it's still reset
+    /// and a `TypeError` carrying the old value is returned.
+    pub fn get_and_reset(self, con: &mut dyn ConnectionLike) -> RedisResult<i64> {
+        let old: Option<String> = Cmd::set(self.key, 0).arg("GET").query(con)?;
+        match old {
+            None => Ok(0),
+            Some(old) => old.parse().map_err(|_| RedisError::from((
+                ErrorKind::TypeError,
+                "Counter held a non-integer value, which was reset",
+                old,
+            ))),
+        }
+    }
+}
+
+/// End of a list to push to or pop from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Where `linsert` puts the value relative to the pivot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertPos {
+    Before,
+    After,
+}
+
+#[derive(Clone, Debug)]
+pub struct ListKey<K: ToRedisArgs> {
+    key: K
+}
+
+impl<K: ToRedisArgs> Key<K> for ListKey<K> {
+    fn new(key: K) -> ListKey<K> {
+        record_key(&key);
+        ListKey { key }
+    }
+
+    fn key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: ToRedisArgs> KeyRef<K> for ListKey<K> {
+    fn key_ref(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: ToRedisArgs> Typed for ListKey<K> {
+    const REDIS_TYPE: RedisType = RedisType::List;
+}
+
+impl<K: ToRedisArgs> GenericValue<K> for ListKey<K> {}
+
+impl<K: ToRedisArgs> Cardinality<K> for ListKey<K> {
+    fn size(self) -> Cmd {
+        self.llen()
+    }
+}
+
+impl<K: ToRedisArgs> ListKey<K> {
+    pub fn lpush<V: ToRedisArgs>(self, values: V) -> Cmd {
+        Cmd::lpush(self.key, values)
+    }
+
+    pub fn rpush<V: ToRedisArgs>(self, values: V) -> Cmd {
+        Cmd::rpush(self.key, values)
+    }
+
+    pub fn push<V: ToRedisArgs>(self, side: Side, values: V) -> Cmd {
+        match side {
+            Side::Left => self.lpush(values),
+            Side::Right => self.rpush(values),
+        }
+    }
+
+    /// Remove and get the first element, nil if the list is empty.
+    pub fn lpop(self) -> Cmd {
+        Cmd::lpop(self.key, None)
+    }
+
+    /// Remove and get the last element, nil if the list is empty.
+    pub fn rpop(self) -> Cmd {
+        Cmd::rpop(self.key, None)
+    }
+
+    /// Remove and get up to `n` elements from the head (Redis 6.2),
+    /// replying a `Vec` that's empty if the list is.
+    pub fn lpop_count(self, n: usize) -> Cmd {
+        let mut cmd = redis::cmd("LPOP");
+        cmd.arg(self.key).arg(n);
+        cmd
+    }
+
+    /// Remove and get up to `n` elements from the tail (Redis 6.2),
+    /// replying a `Vec` that's empty if the list is.
+    pub fn rpop_count(self, n: usize) -> Cmd {
+        let mut cmd = redis::cmd("RPOP");
+        cmd.arg(self.key).arg(n);
+        cmd
+    }
+
+    /// Pop up to `n` elements from the head, in list order.
+    pub fn lpop_many<V: FromRedisValue>(self, n: usize,
+                                        con: &mut dyn ConnectionLike)
+        -> RedisResult<Vec<V>>
+    {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        self.lpop_count(n).query(con)
+    }
+
+    /// Pop up to `n` elements from the tail, last element first.
+    pub fn rpop_many<V: FromRedisValue>(self, n: usize,
+                                        con: &mut dyn ConnectionLike)
+        -> RedisResult<Vec<V>>
+    {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        self.rpop_count(n).query(con)
+    }
+
+    pub fn ltrim(self, start: isize, stop: isize) -> Cmd {
+        Cmd::ltrim(self.key, start, stop)
+    }
+
+    pub fn llen(self) -> Cmd {
+        Cmd::llen(self.key)
+    }
+
+    pub fn lrange(self, start: isize, stop: isize) -> Cmd {
+        Cmd::lrange(self.key, start, stop)
+    }
+
+    /// Pop up to `count` elements from `side` of the first non-empty list
+    /// of `keys` (Redis 7.0), replying `Option<(K, Vec<V>)>` with the key
+    /// popped from, nil if all lists are empty.
+    pub fn lmpop<I: Iterator<Item=ListKey<K>>>(keys: I, side: Side, count: usize) -> Cmd {
+        let keys: Vec<K> = keys.map(|k| k.key).collect();
+        let mut cmd = redis::cmd("LMPOP");
+        cmd.arg(keys.len()).arg(keys);
+        cmd.arg(match side {
+            Side::Left => "LEFT",
+            Side::Right => "RIGHT",
+        });
+        cmd.arg("COUNT").arg(count);
+        cmd
+    }
+
+    /// Insert `value` next to the first occurrence of `pivot`.
+    ///
+    /// Replies the new list length as `i64`, -1 if `pivot` wasn't found.
+    pub fn linsert<P: ToRedisArgs, V: ToRedisArgs>(self, pos: InsertPos,
+                                                   pivot: P, value: V) -> Cmd {
+        match pos {
+            InsertPos::Before => Cmd::linsert_before(self.key, pivot, value),
+            InsertPos::After => Cmd::linsert_after(self.key, pivot, value),
+        }
+    }
+
+    /// `LPUSH` returning the length of the list after the push.
+    pub fn lpush_len<V: ToRedisArgs>(self, values: V, con: &mut dyn ConnectionLike)
+        -> RedisResult<usize>
+    {
+        self.lpush(values).query(con)
+    }
+
+    /// `RPUSH` returning the length of the list after the push.
+    pub fn rpush_len<V: ToRedisArgs>(self, values: V, con: &mut dyn ConnectionLike)
+        -> RedisResult<usize>
+    {
+        self.rpush(values).query(con)
+    }
+
+    /// Push to `side` and trim the list to the `max_len` most recently
+    /// pushed elements in a single `MULTI` transaction.
+    ///
+    /// Returns the length of the list after trimming.
+    pub fn push_capped<V: ToRedisArgs>(self, side: Side, values: V,
+                                       max_len: usize,
+                                       con: &mut dyn ConnectionLike)
+        -> RedisResult<usize>
+        where K: Clone
+    {
+        let max = max_len as isize;
+        let (start, stop) = match (side, max_len) {
+            // start > stop empties the list
+            (_, 0) => (1, 0),
+            (Side::Left, _) => (0, max - 1),
+            (Side::Right, _) => (-max, -1),
+        };
+        let (len,): (usize,) = redis::pipe()
+            .atomic()
+            .add_command(self.clone().push(side, values))
+            .add_command(self.ltrim(start, stop)).ignore()
+            .query(con)?;
+        Ok(len.min(max_len))
+    }
+}
+
+/// End of a sorted set to pop from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScoreEnd {
+    Min,
+    Max,
+}
+
+/// Which members a `ZRANGE`-style command selects (Redis 6.2).
+///
+/// With `rev` the bounds go from high to low, e.g. `by_score("+inf", "10")`.
+#[derive(Clone, Debug)]
+pub struct ZRange {
+    start: String,
+    stop: String,
+    by: Option<&'static str>,
+    rev: bool,
+    limit: Option<(usize, isize)>,
+}
+
+impl ZRange {
+    /// Members from rank `start` to `stop`, negative ranks counting from
+    /// the end.
+    pub fn by_rank(start: isize, stop: isize) -> ZRange {
+        ZRange::new(start.to_string(), stop.to_string(), None)
+    }
+
+    /// Members scored between `min` and `max`, with bounds written like in
+    /// Redis, e.g. `"-inf"` or `"(1.5"` for an exclusive bound.
+    pub fn by_score<S: ToString>(min: S, max: S) -> ZRange {
+        ZRange::new(min.to_string(), max.to_string(), Some("BYSCORE"))
+    }
+
+    /// Members between `min` and `max` in lexicographical order, e.g.
+    /// `"[a"`, `"(c"`, `"-"` or `"+"`. Only sensible when all scores are equal.
+    pub fn by_lex(min: &str, max: &str) -> ZRange {
+        ZRange::new(min.to_string(), max.to_string(), Some("BYLEX"))
+    }
+
+    fn new(start: String, stop: String, by: Option<&'static str>) -> ZRange {
+        ZRange { start, stop, by, rev: false, limit: None }
+    }
+
+    /// Order from the highest score to the lowest.
+    pub fn rev(mut self) -> ZRange {
+        self.rev = true;
+        self
+    }
+
+    /// Skip `offset` members and select at most `count`, all if negative.
+    /// Only allowed with `by_score` and `by_lex`.
+    pub fn limit(mut self, offset: usize, count: isize) -> ZRange {
+        self.limit = Some((offset, count));
+        self
+    }
+}
+
+impl ToRedisArgs for ZRange {
+    fn write_redis_args<W: ?Sized + RedisWrite>(&self, out: &mut W) {
+        self.start.write_redis_args(out);
+        self.stop.write_redis_args(out);
+        if let Some(by) = self.by {
+            out.write_arg(by.as_bytes());
+        }
+        if self.rev {
+            out.write_arg(b"REV");
+        }
+        if let Some((offset, count)) = self.limit {
+            out.write_arg(b"LIMIT");
+            offset.write_redis_args(out);
+            count.write_redis_args(out);
+        }
+    }
+}
+
+const UPDATE_BEST_SCRIPT: &str = "\
+    local current = redis.call('ZSCORE', KEYS[1], ARGV[2])
+    if current and tonumber(current) >= tonumber(ARGV[1]) then
+        return 0
+    end
+    redis.call('ZADD', KEYS[1], ARGV[1], ARGV[2])
+    return 1";
+
+#[derive(Clone, Debug)]
+pub struct SortedSetKey<K: ToRedisArgs> {
+    key: K
+}
+
+impl<K: ToRedisArgs> Key<K> for SortedSetKey<K> {
+    fn new(key: K) -> SortedSetKey<K> {
+        record_key(&key);
+        SortedSetKey { key }
+    }
+
+    fn key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: ToRedisArgs> KeyRef<K> for SortedSetKey<K> {
+    fn key_ref(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: ToRedisArgs> Typed for SortedSetKey<K> {
+    const REDIS_TYPE: RedisType = RedisType::ZSet;
+}
+
+impl<K: ToRedisArgs> GenericValue<K> for SortedSetKey<K> {}
+
+impl<K: ToRedisArgs> Cardinality<K> for SortedSetKey<K> {
+    fn size(self) -> Cmd {
+        self.zcard()
+    }
+}
+
+impl<K: ToRedisArgs> SortedSetKey<K> {
+    pub fn zadd<M: ToRedisArgs>(self, member: M, score: f64) -> Cmd {
+        self.zadd_many(std::iter::once((score, member)))
+    }
+
+    /// Add or update several `(score, member)` pairs in one `ZADD`,
+    /// replying how many members were added.
+    ///
+    /// `pairs` must not be empty.
+    pub fn zadd_many<M, I>(self, pairs: I) -> Cmd
+    where M: ToRedisArgs,
+          I: IntoIterator<Item=(f64, M)>,
+    {
+        let mut cmd = redis::cmd("ZADD");
+        cmd.arg(self.key);
+        for (score, member) in pairs {
+            cmd.arg(score).arg(member);
+        }
+        cmd
+    }
+
+    pub fn zrem<M: ToRedisArgs>(self, member: M) -> Cmd {
+        self.zrem_many(std::iter::once(member))
+    }
+
+    /// Remove several members in one `ZREM`, replying how many were removed.
+    ///
+    /// `members` must not be empty.
+    pub fn zrem_many<M, I>(self, members: I) -> Cmd
+    where M: ToRedisArgs,
+          I: IntoIterator<Item=M>,
+    {
+        let mut cmd = redis::cmd("ZREM");
+        cmd.arg(self.key);
+        for m in members {
+            cmd.arg(m);
+        }
+        cmd
+    }
+
+    pub fn zscore<M: ToRedisArgs>(self, member: M) -> Cmd {
+        Cmd::zscore(self.key, member)
+    }
+
+    pub fn zcard(self) -> Cmd {
+        Cmd::zcard(self.key)
+    }
+
+    /// `member`'s score, `None` if it isn't in the set.
+    pub fn zscore_opt<M: ToRedisArgs>(self, member: M,
+                                      con: &mut dyn ConnectionLike)
+        -> RedisResult<Option<f64>>
+    {
+        self.zscore(member).query(con)
+    }
+
+    /// Scores of several members in one `ZMSCORE` (Redis 6.2), `None` for
+    /// the ones that aren't in the set.
+    pub fn zmscore<M: ToRedisArgs>(self, members: &[M],
+                                   con: &mut dyn ConnectionLike)
+        -> RedisResult<Vec<Option<f64>>>
+    {
+        if members.is_empty() {
+            return Ok(Vec::new());
+        }
+        redis::cmd("ZMSCORE").arg(self.key).arg(members).query(con)
+    }
+
+    /// Members selected by `range`, replying a `Vec` of members.
+    pub fn zrange(self, range: ZRange) -> Cmd {
+        let mut cmd = redis::cmd("ZRANGE");
+        cmd.arg(self.key).arg(range);
+        cmd
+    }
+
+    /// Store the members selected by `range` with their scores in `dest`,
+    /// replacing it, and reply the number stored (Redis 6.2).
+    pub fn zrangestore(self, dest: SortedSetKey<K>, range: ZRange) -> Cmd {
+        let mut cmd = redis::cmd("ZRANGESTORE");
+        cmd.arg(dest.key).arg(self.key).arg(range);
+        cmd
+    }
+
+    /// Members of the first of `keys` that aren't in any of the others
+    /// (Redis 6.2), replying a `Vec` of members, or `Vec<(M, f64)>` pairs
+    /// if `with_scores`.
+    pub fn zdiff<I: Iterator<Item=SortedSetKey<K>>>(keys: I, with_scores: bool) -> Cmd {
+        let keys: Vec<K> = keys.map(|k| k.key).collect();
+        let mut cmd = redis::cmd("ZDIFF");
+        cmd.arg(keys.len()).arg(keys);
+        if with_scores {
+            cmd.arg("WITHSCORES");
+        }
+        cmd
+    }
+
+    /// Store the `zdiff` of `keys` in `dest`, replacing it, and reply the
+    /// number of members stored (Redis 6.2).
+    pub fn zdiffstore<I: Iterator<Item=SortedSetKey<K>>>(dest: SortedSetKey<K>, keys: I) -> Cmd {
+        let keys: Vec<K> = keys.map(|k| k.key).collect();
+        let mut cmd = redis::cmd("ZDIFFSTORE");
+        cmd.arg(dest.key).arg(keys.len()).arg(keys);
+        cmd
+    }
+
+    /// Pop up to `count` members from the `end` of the first non-empty
+    /// sorted set of `keys` (Redis 7.0), nil if all of them are empty.
+    ///
+    /// The reply nests each member with its score, so it parses to
+    /// `Option<(K, Vec<Vec<(M, f64)>>)>` with one pair per inner `Vec`.
+    pub fn zmpop<I: Iterator<Item=SortedSetKey<K>>>(keys: I, end: ScoreEnd, count: usize) -> Cmd {
+        let keys: Vec<K> = keys.map(|k| k.key).collect();
+        let mut cmd = redis::cmd("ZMPOP");
+        cmd.arg(keys.len()).arg(keys);
+        cmd.arg(match end {
+            ScoreEnd::Min => "MIN",
+            ScoreEnd::Max => "MAX",
+        });
+        cmd.arg("COUNT").arg(count);
+        cmd
+    }
+
+    /// Members from `start` to `stop` by descending score, replying
+    /// `Vec<(M, f64)>` pairs.
+    pub fn zrevrange_withscores(self, start: isize, stop: isize) -> Cmd {
+        Cmd::zrevrange_withscores(self.key, start, stop)
+    }
+
+    /// The `n` highest scored members as `(rank, member, score)`, where the
+    /// highest score has rank 0.
+    pub fn top<M: FromRedisValue>(self, n: usize, con: &mut dyn ConnectionLike)
+        -> RedisResult<Vec<(usize, M, f64)>>
+    {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let entries: Vec<(M, f64)> =
+            self.zrevrange_withscores(0, n as isize - 1).query(con)?;
+        Ok(entries.into_iter()
+            .enumerate()
+            .map(|(rank, (member, score))| (rank, member, score))
+            .collect())
+    }
+
+    /// Set `member`'s score to `score` if it's higher than the stored one
+    /// or the member is new, returning whether the score changed.
+    ///
+    /// Uses `ZADD ... GT CH`, which needs Redis 6.2. See `update_best_lua`
+    /// for older servers.
+    pub fn update_best<M: ToRedisArgs>(self, member: M, score: f64,
+                                       con: &mut dyn ConnectionLike)
+        -> RedisResult<bool>
+    {
+        redis::cmd("ZADD")
+            .arg(self.key).arg("GT").arg("CH")
+            .arg(score).arg(member)
+            .query(con)
+    }
+
+    /// Like `update_best`, but compares with `ZSCORE` in a Lua script so
+    /// it works before Redis 6.2.
+    pub fn update_best_lua<M: ToRedisArgs>(self, member: M, score: f64,
+                                           con: &mut dyn ConnectionLike)
+        -> RedisResult<bool>
+    {
+        redis::cmd("EVAL")
+            .arg(UPDATE_BEST_SCRIPT)
+            .arg(1).arg(self.key)
+            .arg(score).arg(member)
+            .query(con)
+    }
+}
+
+const RATE_LIMIT_SCRIPT: &str = "\
+    local now = tonumber(ARGV[1])
+    redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - tonumber(ARGV[2]))
+    local count = redis.call('ZCARD', KEYS[1])
+    if count >= tonumber(ARGV[3]) then
+        return 0
+    end
+    redis.call('ZADD', KEYS[1], now, ARGV[1] .. ':' .. count)
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1";
+
+/// Sliding window rate limiter keeping the timestamps of allowed requests
+/// as the scores of a sorted set.
+#[derive(Clone, Debug)]
+pub struct RateLimiter<K: ToRedisArgs> {
+    key: SortedSetKey<K>,
+}
+
+impl<K: ToRedisArgs> RateLimiter<K> {
+    pub fn new(key: SortedSetKey<K>) -> RateLimiter<K> {
+        RateLimiter { key }
+    }
+
+    /// Whether a request at `now_ms` is allowed, given at most `max` are
+    /// in any `window_ms`. Allowed requests are recorded and expire from
+    /// the window, and the key expires once the window is empty.
+    ///
+    /// Runs in a Lua script, so concurrent callers can't exceed `max`.
+    pub fn allow(&self, now_ms: u64, window_ms: u64, max: usize,
+                 con: &mut dyn ConnectionLike) -> RedisResult<bool>
+    {
+        redis::cmd("EVAL")
+            .arg(RATE_LIMIT_SCRIPT)
+            .arg(1).arg(&self.key.key)
+            .arg(now_ms).arg(window_ms).arg(max)
+            .query(con)
+    }
+}
+
+/// Unit of the range given to `BitmapKey::bitcount_range`. Servers before
+/// Redis 7.0 only count bytes and reject either token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+impl ToRedisArgs for BitUnit {
+    fn write_redis_args<W: ?Sized + RedisWrite>(&self, out: &mut W) {
+        out.write_arg(match self {
+            BitUnit::Byte => b"BYTE",
+            BitUnit::Bit => b"BIT",
+        });
+    }
+}
+
+const CLEAR_RANGE_SCRIPT: &str = "\
+    local start = tonumber(ARGV[1])
+    local last = math.min(tonumber(ARGV[2]), redis.call('STRLEN', KEYS[1]) - 1)
+    if last < start then
+        return
+    end
+    redis.call('SETRANGE', KEYS[1], start, string.rep('\\0', last - start + 1))";
+
+/// A string used as an array of bits.
+///
+/// Bit 0 is the most significant bit of the first byte. Redis grows the
+/// string with zero bytes whenever a bit or byte past its end is written,
+/// so writing to a high offset allocates everything before it.
+#[derive(Clone, Debug)]
+pub struct BitmapKey<K: ToRedisArgs> {
+    key: K
+}
+
+impl<K: ToRedisArgs> Key<K> for BitmapKey<K> {
+    fn new(key: K) -> BitmapKey<K> {
+        record_key(&key);
+        BitmapKey { key }
+    }
+
+    fn key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: ToRedisArgs> KeyRef<K> for BitmapKey<K> {
+    fn key_ref(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: ToRedisArgs> Typed for BitmapKey<K> {
+    const REDIS_TYPE: RedisType = RedisType::String;
+}
+
+impl<K: ToRedisArgs> GenericValue<K> for BitmapKey<K> {}
+
+impl<K: ToRedisArgs> Cardinality<K> for BitmapKey<K> {
+    fn size(self) -> Cmd {
+        Cmd::strlen(self.key)
+    }
+}
+
+impl<K: ToRedisArgs> BitmapKey<K> {
+    pub fn setbit(self, offset: usize, bit: bool) -> Cmd {
+        Cmd::setbit(self.key, offset, bit)
+    }
+
+    pub fn getbit(self, offset: usize) -> Cmd {
+        Cmd::getbit(self.key, offset)
+    }
+
+    /// Overwrite the bytes starting at `offset` with `value`.
+    pub fn setrange<V: ToRedisArgs>(self, offset: usize, value: V) -> Cmd {
+        let mut cmd = redis::cmd("SETRANGE");
+        cmd.arg(self.key).arg(offset).arg(value);
+        cmd
+    }
+
+    /// Count the set bits in the whole bitmap.
+    pub fn bitcount(self) -> Cmd {
+        Cmd::bitcount(self.key)
+    }
+
+    /// Count the set bits from `start` to `end`, both inclusive, in
+    /// `unit`s. Negative offsets count from the end of the bitmap.
+    pub fn bitcount_range(self, start: isize, end: isize, unit: BitUnit) -> Cmd {
+        let mut cmd = redis::cmd("BITCOUNT");
+        cmd.arg(self.key).arg(start).arg(end).arg(unit);
+        cmd
+    }
+
+    /// Zero the bytes from `start_byte` to `end_byte`, both inclusive.
+    ///
+    /// Bytes past the end of the bitmap are already unset, so the range is
+    /// cut off at its end in a Lua script instead of growing the string.
+    pub fn clear_range(self, start_byte: usize, end_byte: usize,
+                       con: &mut dyn ConnectionLike) -> RedisResult<()>
+    {
+        if end_byte < start_byte {
+            return Ok(());
+        }
+        redis::cmd("EVAL")
+            .arg(CLEAR_RANGE_SCRIPT)
+            .arg(1).arg(self.key)
+            .arg(start_byte).arg(end_byte)
+            .query(con)
+    }
+
+    /// Set `count` bits starting at `offset` to `bit` in one transaction.
+    ///
+    /// Whole bytes are written with `SETRANGE`, only the partial bytes at
+    /// either end of the run take a `SETBIT` per bit.
+    pub fn set_all(self, offset: usize, count: usize, bit: bool,
+                   con: &mut dyn ConnectionLike) -> RedisResult<()>
+        where K: Clone
+    {
+        if count == 0 {
+            return Ok(());
+        }
+        let end = offset + count;
+        let first_byte = offset.div_ceil(8);
+        let last_byte = end / 8;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if first_byte >= last_byte {
+            for i in offset..end {
+                pipe.add_command(self.clone().setbit(i, bit)).ignore();
+            }
+        } else {
+            for i in offset..first_byte * 8 {
+                pipe.add_command(self.clone().setbit(i, bit)).ignore();
+            }
+            let fill = if bit { 0xff } else { 0 };
+            let bytes = vec![fill; last_byte - first_byte];
+            pipe.add_command(self.clone().setrange(first_byte, bytes)).ignore();
+            for i in last_byte * 8..end {
+                pipe.add_command(self.clone().setbit(i, bit)).ignore();
+            }
+        }
+        pipe.query(con)
+    }
+}
+
+/// Conditions for the expire commands (Redis 7.0, 7.4 for hash fields).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpireOption {
+    /// Only if there's no expiry yet.
+    Nx,
+    /// Only if there's an expiry already.
+    Xx,
+    /// Only if the new expiry is later than the current one.
+    Gt,
+    /// Only if the new expiry is earlier than the current one.
+    Lt,
+}
+
+impl ToRedisArgs for ExpireOption {
+    fn write_redis_args<W: ?Sized + RedisWrite>(&self, out: &mut W) {
+        out.write_arg(match self {
+            ExpireOption::Nx => b"NX",
+            ExpireOption::Xx => b"XX",
+            ExpireOption::Gt => b"GT",
+            ExpireOption::Lt => b"LT",
+        });
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HashKey<K: ToRedisArgs> {
+    key: K
+}
+
+impl<K: ToRedisArgs> Key<K> for HashKey<K> {
+    fn new(key: K) -> HashKey<K> {
+        record_key(&key);
+        HashKey { key }
+    }
+
+    fn key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: ToRedisArgs> KeyRef<K> for HashKey<K> {
+    fn key_ref(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: ToRedisArgs> Typed for HashKey<K> {
+    const REDIS_TYPE: RedisType = RedisType::Hash;
+}
+
+impl<K: ToRedisArgs> GenericValue<K> for HashKey<K> {}
+
+impl<K: ToRedisArgs> Cardinality<K> for HashKey<K> {
+    fn size(self) -> Cmd {
+        self.hlen()
+    }
+}
+
+impl<K: ToRedisArgs> HashKey<K> {
+    pub fn hset<F: ToRedisArgs, V: ToRedisArgs>(self, field: F, value: V) -> Cmd {
+        Cmd::hset(self.key, field, value)
+    }
+
+    /// `hset`, unless `value` serializes to more than `max_value_bytes`.
+    pub fn checked_hset<F: ToRedisArgs, V: ToRedisArgs>(self, field: F, value: V,
+                                                        max_value_bytes: usize)
+        -> Result<Cmd, Error>
+    {
+        check_value_size(&value, max_value_bytes)?;
+        Ok(self.hset(field, value))
+    }
+
+    pub fn hget<F: ToRedisArgs>(self, field: F) -> Cmd {
+        Cmd::hget(self.key, field)
+    }
+
+    /// Set `field` only if it doesn't exist yet.
+    pub fn hsetnx<F: ToRedisArgs, V: ToRedisArgs>(self, field: F, value: V) -> Cmd {
+        Cmd::hset_nx(self.key, field, value)
+    }
+
+    /// `field`'s value, `None` if the field or the hash is missing.
+    pub fn hget_opt<F: ToRedisArgs, V: FromRedisValue>(self, field: F,
+                                                       con: &mut dyn ConnectionLike)
+        -> RedisResult<Option<V>>
+    {
+        self.hget(field).query(con)
+    }
+
+    /// `hsetnx` returning whether the field was newly set.
+    pub fn hsetnx_ok<F: ToRedisArgs, V: ToRedisArgs>(self, field: F, value: V,
+                                                     con: &mut dyn ConnectionLike)
+        -> RedisResult<bool>
+    {
+        self.hsetnx(field, value).query(con)
+    }
+
+    pub fn hdel<F: ToRedisArgs>(self, field: F) -> Cmd {
+        Cmd::hdel(self.key, field)
+    }
+
+    pub fn hgetall(self) -> Cmd {
+        Cmd::hgetall(self.key)
+    }
+
+    pub fn hlen(self) -> Cmd {
+        Cmd::hlen(self.key)
+    }
+
+    /// Expire `fields` in `ttl_secs` seconds (Redis 7.4).
+    ///
+    /// Replies a `Vec<i64>` with one code per field: -2 if the field is
+    /// missing, 0 if `opt` wasn't met, 1 if the expiry was set and 2 if
+    /// the field was deleted right away because `ttl_secs` is 0.
+    pub fn hexpire<F: ToRedisArgs>(self, fields: &[F], ttl_secs: usize,
+                                   opt: Option<ExpireOption>) -> Cmd {
+        let mut cmd = redis::cmd("HEXPIRE");
+        cmd.arg(self.key).arg(ttl_secs).arg(opt)
+            .arg("FIELDS").arg(fields.len()).arg(fields);
+        cmd
+    }
+
+    /// Get the remaining TTL of `fields` in seconds (Redis 7.4).
+    ///
+    /// Replies a `Vec<i64>` with one entry per field: the TTL, -1 if the
+    /// field has no expiry or -2 if it's missing.
+    pub fn httl<F: ToRedisArgs>(self, fields: &[F]) -> Cmd {
+        let mut cmd = redis::cmd("HTTL");
+        cmd.arg(self.key).arg("FIELDS").arg(fields.len()).arg(fields);
+        cmd
+    }
+
+    /// Remove the expiry of `fields` (Redis 7.4).
+    ///
+    /// Replies a `Vec<i64>` with one code per field: 1 if the expiry was
+    /// removed, -1 if the field had none or -2 if it's missing.
+    pub fn hpersist<F: ToRedisArgs>(self, fields: &[F]) -> Cmd {
+        let mut cmd = redis::cmd("HPERSIST");
+        cmd.arg(self.key).arg("FIELDS").arg(fields.len()).arg(fields);
+        cmd
+    }
+}
+
+/// A pub/sub channel.
+#[derive(Clone, Debug)]
+pub struct Channel<K: ToRedisArgs> {
+    name: K
+}
+
+impl<K: ToRedisArgs> Channel<K> {
+    pub fn new(name: K) -> Channel<K> {
+        Channel { name }
+    }
+
+    pub fn name(self) -> K {
+        self.name
+    }
+
+    /// Replies the number of subscribers that received `message`.
+    pub fn publish<M: ToRedisArgs>(self, message: M) -> Cmd {
+        Cmd::publish(self.name, message)
+    }
+}
+
+/// A message received by a `Subscriber`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message<V> {
+    pub channel: String,
+    /// The pattern that matched `channel` if it came from `psubscribe`.
+    pub pattern: Option<String>,
+    pub payload: V,
+}
+
+/// A connection in pub/sub mode whose subscriptions can change over time.
+///
+/// Dropping it unsubscribes from everything and returns the connection to
+/// normal mode.
+pub struct Subscriber<'a> {
+    pubsub: PubSub<'a>,
+}
+
+impl<'a> Subscriber<'a> {
+    pub fn new(con: &'a mut Connection) -> Subscriber<'a> {
+        Subscriber { pubsub: con.as_pubsub() }
+    }
+
+    pub fn subscribe<K: ToRedisArgs>(&mut self, channel: Channel<K>) -> RedisResult<()> {
+        self.pubsub.subscribe(channel.name())
+    }
+
+    pub fn unsubscribe<K: ToRedisArgs>(&mut self, channel: Channel<K>) -> RedisResult<()> {
+        self.pubsub.unsubscribe(channel.name())
+    }
+
+    pub fn psubscribe<P: ToRedisArgs>(&mut self, pattern: P) -> RedisResult<()> {
+        self.pubsub.psubscribe(pattern)
+    }
+
+    pub fn punsubscribe<P: ToRedisArgs>(&mut self, pattern: P) -> RedisResult<()> {
+        self.pubsub.punsubscribe(pattern)
+    }
+
+    /// Limit how long `next_message` blocks, `None` to wait forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> RedisResult<()> {
+        self.pubsub.set_read_timeout(timeout)
+    }
+
+    /// Wait for the next message and parse its payload as `V`.
+    pub fn next_message<V: FromRedisValue>(&mut self) -> RedisResult<Message<V>> {
+        let msg = self.pubsub.get_message()?;
+        Ok(Message {
+            channel: msg.get_channel()?,
+            pattern: msg.get_pattern()?,
+            payload: msg.get_payload()?,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Schema;
+    impl Schema {
+        pub fn myset1(self) -> SetKey<String> {
+            SetKey::new("myset1".to_string())
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let s = Schema;
+        assert_eq!(render(&s.myset1().sadd("x")), "SADD myset1 x");
+        assert_eq!(render(&s.myset1().del()), "DEL myset1");
+        assert_eq!(render(&StringKey::new("greeting").set("hi there")),
+                   r#"SET greeting "hi there""#);
+
+        let keys = vec![StringKey::new("a"), StringKey::new("b")];
+        assert_eq!(render(&mget(keys.into_iter())), "MGET a b");
+    }
+
+    #[test]
+    fn test_render_escapes() {
+        let key = StringKey::new("k");
+        assert_eq!(render(&key.clone().set("")), r#"SET k """#);
+        assert_eq!(render(&key.clone().set(r#"say "hi""#)),
+                   r#"SET k "say \"hi\"""#);
+        assert_eq!(render(&key.clone().set("a\nb")), r#"SET k "a\nb""#);
+        assert_eq!(render(&key.set(&[0xffu8, b'a', 0x00][..])),
+                   r#"SET k "\xffa\x00""#);
+    }
+
+    fn arg_strings(cmd: &Cmd) -> Vec<String> {
+        args(cmd).into_iter()
+            .map(|a| String::from_utf8(a).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_del_args() {
+        assert_eq!(arg_strings(&Schema.myset1().del()), ["DEL", "myset1"]);
+    }
+
+    #[test]
+    fn test_sadd_args() {
+        assert_eq!(arg_strings(&Schema.myset1().sadd("first")),
+                   ["SADD", "myset1", "first"]);
+    }
+
+    #[test]
+    fn test_mget_args() {
+        let keys = vec![IntKey::new("a"), IntKey::new("b"), IntKey::new("c")];
+        assert_eq!(arg_strings(&mget(keys.into_iter())),
+                   ["MGET", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_sunion_args() {
+        let keys = vec![SetKey::new("s1"), SetKey::new("s2")];
+        assert_eq!(arg_strings(&SetKey::sunion(keys.into_iter())),
+                   ["SUNION", "s1", "s2"]);
+    }
+
+    #[test]
+    fn test_binary_args() {
+        let cmd = StringKey::new(&b"k\x00"[..]).set(&[0xffu8][..]);
+        assert_eq!(args(&cmd), [b"SET".to_vec(), b"k\x00".to_vec(), vec![0xff]]);
+    }
+
+    #[test]
+    fn test_connection_args() {
+        assert_eq!(arg_strings(&reset()), ["RESET"]);
+        assert_eq!(arg_strings(&client::client_no_evict(true)),
+                   ["CLIENT", "NO-EVICT", "ON"]);
+        assert_eq!(arg_strings(&client::client_no_evict(false)),
+                   ["CLIENT", "NO-EVICT", "OFF"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_client_name() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        client::client_setname("schema-test").query::<()>(&mut con)?;
+        let name: Option<String> = client::client_getname().query(&mut con)?;
+        assert_eq!(Some("schema-test".to_string()), name);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_sismember_map() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let set = || SetKey::new("members1");
+        set().del().query::<()>(&mut con)?;
+        set().sadd("a").query::<()>(&mut con)?;
+        set().sadd("c").query::<()>(&mut con)?;
+
+        let found = set().sismember_map(vec!["a", "b", "c"], &mut con)?;
+        assert_eq!(3, found.len());
+        assert!(found["a"]);
+        assert!(!found["b"]);
+        assert!(found["c"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_args() {
+        let list = ListKey::new("queue");
+        assert_eq!(arg_strings(&list.clone().push(Side::Left, "a")),
+                   ["LPUSH", "queue", "a"]);
+        assert_eq!(arg_strings(&list.push(Side::Right, &["a", "b"])),
+                   ["RPUSH", "queue", "a", "b"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_push_capped() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let list = ListKey::new("mylist1");
+        list.clone().del().query::<()>(&mut con)?;
+        assert_eq!(2, list.clone().rpush_len(&["a", "b"], &mut con)?);
+        assert_eq!(3, list.clone().lpush_len("c", &mut con)?);
+
+        let len = list.clone()
+            .push_capped(Side::Right, &["d", "e", "f"], 4, &mut con)?;
+        assert_eq!(4, len);
+        let llen: usize = list.llen().query(&mut con)?;
+        assert_eq!(4, llen);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recorder() {
+        let tenant = 42;
+        let recorder = Recorder::start();
+        Schema.myset1().sadd("x");
+        StringKey::new(format!("tenant:{}:name", tenant)).get::<String>();
+        IntKey::new(format!("tenant:{}:visits", tenant)).incr(1);
+
+        let keys: Vec<String> = recorder.keys().into_iter().collect();
+        assert_eq!(keys, ["myset1", "tenant:42:name", "tenant:42:visits"]);
+        drop(recorder);
+
+        let recorder = Recorder::start();
+        assert!(recorder.keys().is_empty());
+        StringKey::new("src").copy_to_db(StringKey::new("dest"), 1);
+        let keys: Vec<String> = recorder.keys().into_iter().collect();
+        assert_eq!(keys, ["dest", "src"]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "nested Recorder dropped out of order")]
+    fn test_recorder_out_of_order() {
+        let outer = Recorder::start();
+        let inner = Recorder::start();
+        drop(outer);
+        drop(inner);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_refresh_ttl() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let session = StringKey::new("session1");
+        let missing = StringKey::new("session2");
+        session.clone().set("data").query::<()>(&mut con)?;
+        missing.clone().del().query::<()>(&mut con)?;
+
+        assert!(session.clone().refresh_ttl(60, &mut con)?);
+        assert!(!missing.clone().refresh_ttl(60, &mut con)?);
+        let exists: bool = Cmd::exists("session2").query(&mut con)?;
+        assert!(!exists);
+
+        assert!(session.clone().persist_ok(&mut con)?);
+        assert!(!session.persist_ok(&mut con)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_get_and_reset() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let counter = || IntKey::new("counter1");
+        counter().del().query::<()>(&mut con)?;
+        assert_eq!(0, counter().get_and_reset(&mut con)?);
+
+        counter().incr(3).query::<()>(&mut con)?;
+        counter().incr(4).query::<()>(&mut con)?;
+        assert_eq!(7, counter().get_and_reset(&mut con)?);
+        let now: i64 = counter().get::<i64>().query(&mut con)?;
+        assert_eq!(0, now);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_and_reset_non_integer() {
+        let mut con = StaticReply(Value::Data(b"ok".to_vec()));
+        let err = IntKey::new("k").get_and_reset(&mut con).unwrap_err();
+        assert_eq!(ErrorKind::TypeError, err.kind());
+        assert_eq!(Some("ok"), err.detail());
+    }
+
+    #[test]
+    fn test_cloned_key() {
+        let set = Schema.myset1();
+        let key = set.cloned_key();
+        let archive = SetKey::new(format!("{}:archive", key));
+
+        assert_eq!(render(&set.del()), "DEL myset1");
+        assert_eq!(render(&archive.sadd(key)), "SADD myset1:archive myset1");
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_add_all() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let members: Vec<String> = (0..200).map(|i| format!("m{}", i)).collect();
+        let single = SetKey::new("addall_single");
+        let batched = SetKey::new("addall_batched");
+        single.clone().del().query::<()>(&mut con)?;
+        batched.clone().del().query::<()>(&mut con)?;
+
+        assert_eq!(200, single.clone().add_all(&members, 1000, &mut con)?);
+        assert_eq!(200, batched.clone().add_all(&members, 16, &mut con)?);
+
+        let mut a: Vec<String> = single.smembers().query(&mut con)?;
+        let mut b: Vec<String> = batched.smembers().query(&mut con)?;
+        a.sort();
+        b.sort();
+        assert_eq!(a, b);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_move_across_db() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+        let client1 = redis::Client::open("redis://127.0.0.1/1")?;
+        let mut con1 = client1.get_connection()?;
+
+        let src = || StringKey::new("moving1");
+        let dest = || StringKey::new("moved1");
+        src().set("value").query::<()>(&mut con)?;
+        dest().del().query::<()>(&mut con1)?;
+
+        assert!(src().move_across_db(dest(), 1, &mut con)?);
+        let moved: Option<String> = dest().get::<String>().query(&mut con1)?;
+        assert_eq!(Some("value".to_string()), moved);
+        let left: Option<String> = src().get::<String>().query(&mut con)?;
+        assert_eq!(None, left);
+
+        assert!(!src().move_across_db(dest(), 1, &mut con)?);
+
+        src().set("again").query::<()>(&mut con)?;
+        assert!(!src().move_across_db_atomic(dest(), 1, &mut con)?);
+        dest().del().query::<()>(&mut con1)?;
+        assert!(src().move_across_db_atomic(dest(), 1, &mut con)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_redis_type_parse() -> RedisResult<()> {
+        let parse = |name: &str| {
+            RedisType::from_redis_value(&Value::Status(name.to_string()))
+        };
+        assert_eq!(RedisType::None, parse("none")?);
+        assert_eq!(RedisType::ZSet, parse("zset")?);
+        assert_eq!(RedisType::Other("ReJSON-RL".to_string()), parse("ReJSON-RL")?);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_as_checked() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let set = || SetKey::new("checked1");
+        set().del().query::<()>(&mut con)?;
+        set().sadd("first").query::<()>(&mut con)?;
+
+        let as_string = StringKey::new("checked1")
+            .as_checked::<StringKey<_>>(&mut con)?;
+        assert!(as_string.is_none());
+        let as_set = StringKey::new("checked1")
+            .as_checked::<SetKey<_>>(&mut con)?;
+        assert!(as_set.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sadd_many_args() {
+        assert_eq!(arg_strings(&Schema.myset1().sadd_many(["a", "b", "c"])),
+                   ["SADD", "myset1", "a", "b", "c"]);
+        assert_eq!(arg_strings(&Schema.myset1().srem_many(vec![1, 2])),
+                   ["SREM", "myset1", "1", "2"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_sadd_many() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let set = || SetKey::new("tags1");
+        set().del().query::<()>(&mut con)?;
+        let tags = ["red", "green", "blue", "cyan", "magenta"];
+        let added: usize = set().sadd_many(tags).query(&mut con)?;
+        assert_eq!(5, added);
+        let card: usize = set().scard().query(&mut con)?;
+        assert_eq!(5, card);
+
+        let removed: usize = set().srem_many(["red", "nope"]).query(&mut con)?;
+        assert_eq!(1, removed);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_migrate_key() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut src = client.get_connection()?;
+        let client1 = redis::Client::open("redis://127.0.0.1/1")?;
+        let mut dst = client1.get_connection()?;
+
+        // DUMP/RESTORE don't care about the type, any GenericValue will do
+        let key = || StringKey::new("migrating1");
+        key().del().query::<()>(&mut dst)?;
+        Cmd::hset_multiple("migrating1", &[("a", "1"), ("b", "2")])
+            .query::<()>(&mut src)?;
+        key().expire(100).query::<()>(&mut src)?;
+
+        let outcome = migrate_key(&mut src, &mut dst, key(), None, false, false)?;
+        assert_eq!(MigrateOutcome::Migrated, outcome);
+        let fields: HashMap<String, String> =
+            Cmd::hgetall("migrating1").query(&mut dst)?;
+        assert_eq!(Some(&"2".to_string()), fields.get("b"));
+        let ttl: i64 = key().ttl().query(&mut dst)?;
+        assert!(ttl > 0 && ttl <= 100);
+
+        let outcome = migrate_key(&mut src, &mut dst, key(), None, false, true)?;
+        assert_eq!(MigrateOutcome::DestinationExisted, outcome);
+        let outcome = migrate_key(&mut src, &mut dst, key(), None, true, true)?;
+        assert_eq!(MigrateOutcome::Migrated, outcome);
+        let outcome = migrate_key(&mut src, &mut dst, key(), None, true, true)?;
+        assert_eq!(MigrateOutcome::SourceMissing, outcome);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_approx_size() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let set = || SetKey::new("sized_set1");
+        set().del().query::<()>(&mut con)?;
+        set().sadd_many(["a", "b", "c"]).query::<()>(&mut con)?;
+        assert_eq!(3, set().approx_size(&mut con)?);
+
+        let string = StringKey::new("sized1");
+        string.clone().set("héllo").query::<()>(&mut con)?;
+        assert_eq!(6, string.approx_size(&mut con)?);
+
+        let missing = ListKey::new("sized2");
+        missing.clone().del().query::<()>(&mut con)?;
+        assert_eq!(0, missing.approx_size(&mut con)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_linsert_args() {
+        let list = ListKey::new("letters");
+        assert_eq!(arg_strings(&list.clone().linsert(InsertPos::Before, "c", "b")),
+                   ["LINSERT", "letters", "BEFORE", "c", "b"]);
+        assert_eq!(arg_strings(&list.linsert(InsertPos::After, "a", "b")),
+                   ["LINSERT", "letters", "AFTER", "a", "b"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_linsert() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let list = || ListKey::new("letters1");
+        list().del().query::<()>(&mut con)?;
+        list().rpush(&["a", "c"]).query::<()>(&mut con)?;
+
+        let len: i64 = list().linsert(InsertPos::Before, "c", "b").query(&mut con)?;
+        assert_eq!(3, len);
+        let items: Vec<String> = list().lrange(0, -1).query(&mut con)?;
+        assert_eq!(vec!["a", "b", "c"], items);
+
+        let len: i64 = list().linsert(InsertPos::After, "z", "x").query(&mut con)?;
+        assert_eq!(-1, len);
+
+        Ok(())
+    }
+
+    /// Replies with the same value to every command.
+    struct StaticReply(Value);
+
+    impl ConnectionLike for StaticReply {
+        fn req_packed_command(&mut self, _cmd: &[u8]) -> RedisResult<Value> {
+            Ok(self.0.clone())
+        }
+
+        fn req_packed_commands(&mut self, _cmd: &[u8], _offset: usize,
+                               count: usize) -> RedisResult<Vec<Value>> {
+            Ok(vec![self.0.clone(); count])
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            true
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+    }
+
+    /// Fails with `error` for the first `failures` requests, then replies "ok".
+    struct FlakyConnection {
+        failures: usize,
+        error: fn() -> RedisError,
+        attempts: usize,
+    }
+
+    impl ConnectionLike for FlakyConnection {
+        fn req_packed_command(&mut self, _cmd: &[u8]) -> RedisResult<Value> {
+            self.attempts += 1;
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err((self.error)());
+            }
+            Ok(Value::Data(b"ok".to_vec()))
+        }
+
+        fn req_packed_commands(&mut self, cmd: &[u8], _offset: usize,
+                               count: usize) -> RedisResult<Vec<Value>> {
+            let reply = self.req_packed_command(cmd)?;
+            Ok(vec![reply; count])
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            true
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_with_retry() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            backoff: Duration::from_millis(1),
+        };
+        let reset = || std::io::Error::from(std::io::ErrorKind::ConnectionReset).into();
+
+        // a dropped connection keeps failing, like a real `Connection`
+        let mut con = FlakyConnection { failures: usize::MAX, error: reset, attempts: 0 };
+        let result: RedisResult<String> = with_retry(policy, || {
+            StringKey::new("k").get::<String>().query(&mut con)
+        });
+        assert!(result.is_err());
+        assert_eq!(5, con.attempts);
+
+        // reconnecting on every attempt gets past it
+        let mut connects = 0;
+        let value: String = with_retry(policy, || {
+            connects += 1;
+            let failures = if connects < 3 { usize::MAX } else { 0 };
+            let mut con = FlakyConnection { failures, error: reset, attempts: 0 };
+            StringKey::new("k").get::<String>().query(&mut con)
+        }).unwrap();
+        assert_eq!("ok", value);
+        assert_eq!(3, connects);
+
+        let mut con = FlakyConnection {
+            failures: 2,
+            error: || (ErrorKind::ResponseError, "syntax error").into(),
+            attempts: 0,
+        };
+        let result: RedisResult<String> = with_retry(policy, || {
+            StringKey::new("k").get::<String>().query(&mut con)
+        });
+        assert!(result.is_err());
+        assert_eq!(1, con.attempts);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_update_best() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let board = || SortedSetKey::new("leaderboard1");
+        board().del().query::<()>(&mut con)?;
+        board().zadd("alice", 10.0).query::<()>(&mut con)?;
+
+        assert!(board().update_best("alice", 15.0, &mut con)?);
+        assert!(!board().update_best("alice", 12.0, &mut con)?);
+        let score: f64 = board().zscore("alice").query(&mut con)?;
+        assert_eq!(15.0, score);
+
+        assert!(board().update_best_lua("alice", 20.0, &mut con)?);
+        assert!(!board().update_best_lua("alice", 20.0, &mut con)?);
+        assert!(board().update_best_lua("bob", 1.0, &mut con)?);
+        let score: f64 = board().zscore("alice").query(&mut con)?;
+        assert_eq!(20.0, score);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_bitmap_ranges() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let bits = || BitmapKey::new("bitmap1");
+        bits().del().query::<()>(&mut con)?;
+
+        // bits 3..23: five in byte 0, all of byte 1, seven in byte 2
+        bits().set_all(3, 20, true, &mut con)?;
+        let count: usize = bits().bitcount().query(&mut con)?;
+        assert_eq!(20, count);
+        let first: bool = bits().getbit(2).query(&mut con)?;
+        assert!(!first);
+
+        bits().clear_range(1, 1, &mut con)?;
+        let count: usize = bits().bitcount().query(&mut con)?;
+        assert_eq!(12, count);
+
+        bits().set_all(4, 2, false, &mut con)?;
+        let count: usize = bits().bitcount().query(&mut con)?;
+        assert_eq!(10, count);
+
+        // clearing past the end doesn't grow the bitmap
+        bits().clear_range(2, 1 << 20, &mut con)?;
+        let count: usize = bits().bitcount().query(&mut con)?;
+        assert_eq!(3, count);
+        assert_eq!(3, bits().approx_size(&mut con)?);
+        bits().clear_range(10, 20, &mut con)?;
+        assert_eq!(3, bits().approx_size(&mut con)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "debug-commands")]
+    fn test_debug_object_parse() {
+        let list = DebugObject::parse(
+            "Value at:0x7f5c refcount:1 encoding:quicklist serializedlength:19 \
+             lru:1 lru_seconds_idle:3 ql_nodes:2 ql_avg_node:1.50");
+        assert_eq!(Some(1), list.refcount);
+        assert_eq!(Some("quicklist".to_string()), list.encoding);
+        assert_eq!(Some(19), list.serialized_length);
+        assert_eq!(Some(2), list.ql_nodes);
+        assert_eq!(Some(&"1.50".to_string()), list.fields.get("ql_avg_node"));
+
+        let string = DebugObject::parse(
+            "Value at:0x7f5c refcount:1 encoding:embstr serializedlength:6");
+        assert_eq!(Some("embstr".to_string()), string.encoding);
+        assert_eq!(None, string.ql_nodes);
+        assert_eq!(None, string.lru_seconds_idle);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-commands")]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_debug_object() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let list = || ListKey::new("debugged1");
+        list().del().query::<()>(&mut con)?;
+        // since Redis 7.2 small lists are listpacks, which have no ql_*
+        // fields, so push well past the default 8kb listpack limit
+        let values: Vec<String> = (0..1000).map(|i| format!("{:0100}", i)).collect();
+        list().rpush(&values).query::<()>(&mut con)?;
+
+        let info = list().debug_object(&mut con)?;
+        assert_eq!(Some("quicklist".to_string()), info.encoding);
+        assert!(info.ql_nodes.ok_or("no ql_nodes")? > 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hexpire_args() {
+        let hash = HashKey::new("sessions");
+        assert_eq!(arg_strings(&hash.clone().hexpire(&["a", "b"], 60, None)),
+                   ["HEXPIRE", "sessions", "60", "FIELDS", "2", "a", "b"]);
+        assert_eq!(arg_strings(&hash.clone().hexpire(&["a"], 60, Some(ExpireOption::Gt))),
+                   ["HEXPIRE", "sessions", "60", "GT", "FIELDS", "1", "a"]);
+        assert_eq!(arg_strings(&hash.httl(&["a"])),
+                   ["HTTL", "sessions", "FIELDS", "1", "a"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis 7.4 server at 127.0.0.1"]
+    fn test_hexpire() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let hash = || HashKey::new("hexpire1");
+        hash().del().query::<()>(&mut con)?;
+        hash().hset("a", "1").query::<()>(&mut con)?;
+        hash().hset("b", "2").query::<()>(&mut con)?;
+
+        let set: Vec<i64> = hash().hexpire(&["a", "c"], 100, None).query(&mut con)?;
+        assert_eq!(vec![1, -2], set);
+        let ttls: Vec<i64> = hash().httl(&["a", "b"]).query(&mut con)?;
+        assert!(ttls[0] > 0 && ttls[0] <= 100);
+        assert_eq!(-1, ttls[1]);
+
+        let persisted: Vec<i64> = hash().hpersist(&["a"]).query(&mut con)?;
+        assert_eq!(vec![1], persisted);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_assert_encoding() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let set = || SetKey::new("encoded1");
+        set().del().query::<()>(&mut con)?;
+        assert!(set().assert_encoding(Encoding::IntSet, &mut con).is_err());
+
+        set().sadd_many([1, 2, 3]).query::<()>(&mut con)?;
+        set().assert_encoding(Encoding::IntSet, &mut con)?;
+
+        set().sadd("not a number").query::<()>(&mut con)?;
+        let err = set().assert_encoding(Encoding::IntSet, &mut con)
+            .unwrap_err();
+        assert_eq!(ErrorKind::TypeError, err.kind());
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_top() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let board = || SortedSetKey::new("leaderboard2");
+        board().del().query::<()>(&mut con)?;
+        for (member, score) in [("a", 1.0), ("b", 5.0), ("c", 3.0), ("d", 4.0), ("e", 2.0)] {
+            board().zadd(member, score).query::<()>(&mut con)?;
+        }
+
+        let top: Vec<(usize, String, f64)> = board().top(3, &mut con)?;
+        assert_eq!(vec![
+            (0, "b".to_string(), 5.0),
+            (1, "d".to_string(), 4.0),
+            (2, "c".to_string(), 3.0),
+        ], top);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_get_touch() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let cached = || StringKey::new("cached1");
+        cached().set("page").query::<()>(&mut con)?;
+        cached().expire(10).query::<()>(&mut con)?;
+
+        let value: Option<String> = cached().get_touch(100, &mut con)?;
+        assert_eq!(Some("page".to_string()), value);
+        let ttl: i64 = cached().ttl().query(&mut con)?;
+        assert!(ttl > 10);
+
+        cached().del().query::<()>(&mut con)?;
+        let value: Option<String> = cached().get_touch(100, &mut con)?;
+        assert_eq!(None, value);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zadd_many_args() {
+        let board = SortedSetKey::new("board");
+        assert_eq!(arg_strings(&board.clone().zadd_many([(1.5, "a"), (2.0, "b")])),
+                   ["ZADD", "board", "1.5", "a", "2.0", "b"]);
+        assert_eq!(arg_strings(&board.zrem_many(["a", "b"])),
+                   ["ZREM", "board", "a", "b"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_zadd_many() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let board = || SortedSetKey::new("leaderboard3");
+        board().del().query::<()>(&mut con)?;
+        let pairs = (0..50).map(|i| (i as f64, format!("player{}", i)));
+        let added: usize = board().zadd_many(pairs).query(&mut con)?;
+        assert_eq!(50, added);
+        let card: usize = board().zcard().query(&mut con)?;
+        assert_eq!(50, card);
+
+        let removed: usize = board().zrem_many(["player0", "player1"]).query(&mut con)?;
+        assert_eq!(2, removed);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "pool")]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_schema_client() -> Result<(), Box<dyn std::error::Error>> {
+        let client = SchemaClient::open("redis://127.0.0.1/")?;
+
+        let key = || StringKey::new("pooled1");
+        client.query::<()>(&key().set("value"))?;
+        let value: String = client.query(&key().get::<String>())?;
+        assert_eq!("value", value);
+
+        let value: Option<String> = client.with_conn(|con| key().get_touch(60, con))?;
+        assert_eq!(Some("value".to_string()), value);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_expire_many() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let keys = ["expiring1", "expiring2", "expiring3"];
+        for k in keys {
+            StringKey::new(k).set("x").query::<()>(&mut con)?;
+        }
+        StringKey::new("expiring3").del().query::<()>(&mut con)?;
+
+        let applied = expire_many(keys.into_iter().map(StringKey::new), 60, &mut con)?;
+        assert_eq!(2, applied);
+        let ttl: i64 = StringKey::new("expiring1").ttl().query(&mut con)?;
+        assert!(ttl > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_type_census() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        for i in 0..3 {
+            StringKey::new(format!("census:string{}", i)).set("x").query::<()>(&mut con)?;
+        }
+        for i in 0..2 {
+            let set = SetKey::new(format!("census:set{}", i));
+            set.clone().del().query::<()>(&mut con)?;
+            set.sadd("x").query::<()>(&mut con)?;
+        }
+        let hash = HashKey::new("census:hash0");
+        hash.clone().del().query::<()>(&mut con)?;
+        hash.hset("f", "v").query::<()>(&mut con)?;
+
+        let census = type_census("census:*", &mut con)?;
+        assert_eq!(3, census.len());
+        assert_eq!(Some(&3), census.get(&RedisType::String));
+        assert_eq!(Some(&2), census.get(&RedisType::Set));
+        assert_eq!(Some(&1), census.get(&RedisType::Hash));
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_sync_to() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let set = || SetKey::new("synced1");
+        set().del().query::<()>(&mut con)?;
+        set().sadd_many(["a", "b"]).query::<()>(&mut con)?;
+
+        let desired = ["b", "c"].map(String::from);
+        assert_eq!((1, 1), set().sync_to(desired.clone(), &mut con)?);
+        let mut members: Vec<String> = set().smembers().query(&mut con)?;
+        members.sort();
+        assert_eq!(vec!["b", "c"], members);
+
+        assert_eq!((0, 0), set().sync_to(desired, &mut con)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_zscore_opt() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let board = || SortedSetKey::new("leaderboard4");
+        board().del().query::<()>(&mut con)?;
+        board().zadd_many([(1.5, "a"), (3.0, "c")]).query::<()>(&mut con)?;
+
+        assert_eq!(Some(1.5), board().zscore_opt("a", &mut con)?);
+        assert_eq!(None, board().zscore_opt("b", &mut con)?);
+        assert_eq!(vec![Some(1.5), None, Some(3.0)],
+                   board().zmscore(&["a", "b", "c"], &mut con)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_instrumentation() {
+        let observed = RefCell::new(Vec::new());
+        let hook = |command: &str, _elapsed: Duration| {
+            observed.borrow_mut().push(command.to_string());
+        };
+        let mut stub = StaticReply(Value::Data(b"ok".to_vec()));
+        let mut con = Instrumented::new(&mut stub, &hook);
+
+        let value: String = StringKey::new("k").get::<String>().query(&mut con).unwrap();
+        assert_eq!("ok", value);
+        Schema.myset1().sadd("x").query::<()>(&mut con).unwrap();
+        assert_eq!(vec!["GET", "SADD"], *observed.borrow());
+    }
+
+    #[test]
+    #[cfg(feature = "pool")]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_schema_client_instrumentation() -> Result<(), Box<dyn std::error::Error>> {
+        let observed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = observed.clone();
+        let client = SchemaClient::open("redis://127.0.0.1/")?
+            .with_instrumentation(move |command: &str, _elapsed: Duration| {
+                recorded.lock().unwrap().push(command.to_string());
+            });
+
+        client.query::<Option<String>>(&StringKey::new("pooled1").get::<String>())?;
+        assert_eq!(vec!["GET"], *observed.lock().unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_lpop_many() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let jobs = || ListKey::new("jobs1");
+        jobs().del().query::<()>(&mut con)?;
+        jobs().rpush(&["1", "2", "3", "4", "5"]).query::<()>(&mut con)?;
+
+        let batch: Vec<String> = jobs().lpop_many(3, &mut con)?;
+        assert_eq!(vec!["1", "2", "3"], batch);
+        let len: usize = jobs().llen().query(&mut con)?;
+        assert_eq!(2, len);
+
+        let last: Option<String> = jobs().rpop().query(&mut con)?;
+        assert_eq!(Some("5".to_string()), last);
+        jobs().del().query::<()>(&mut con)?;
+        let empty: Vec<String> = jobs().lpop_many(3, &mut con)?;
+        assert!(empty.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_subscriber() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+        let mut sub_con = client.get_connection()?;
+
+        let news = || Channel::new("news");
+        let sports = || Channel::new("sports");
+        let mut subscriber = Subscriber::new(&mut sub_con);
+        subscriber.subscribe(news())?;
+        subscriber.subscribe(sports())?;
+        subscriber.unsubscribe(sports())?;
+        subscriber.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        sports().publish("goal").query::<()>(&mut con)?;
+        news().publish("headline").query::<()>(&mut con)?;
+
+        let msg: Message<String> = subscriber.next_message()?;
+        assert_eq!("news", msg.channel);
+        assert_eq!(None, msg.pattern);
+        assert_eq!("headline", msg.payload);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checked_set() {
+        let key = StringKey::new("blob");
+        let cmd = key.clone().checked_set("small", 8).unwrap();
+        assert_eq!(render(&cmd), "SET blob small");
+
+        let err = key.checked_set(vec![0u8; 9], 8).err();
+        assert_eq!(Some(Error::ValueTooLarge { size: 9, max: 8 }), err);
+
+        let hash = HashKey::new("blobs");
+        assert!(hash.clone().checked_hset("f", "12345678", 8).is_ok());
+        assert!(hash.checked_hset("f", "123456789", 8).is_err());
+    }
+
+    #[test]
+    fn test_zrange_args() {
+        let board = || SortedSetKey::new("board");
+        assert_eq!(arg_strings(&board().zrange(ZRange::by_rank(0, -1))),
+                   ["ZRANGE", "board", "0", "-1"]);
+        assert_eq!(arg_strings(&board().zrange(ZRange::by_score("+inf", "(10").rev().limit(5, 10))),
+                   ["ZRANGE", "board", "+inf", "(10", "BYSCORE", "REV", "LIMIT", "5", "10"]);
+        assert_eq!(arg_strings(&board().zrangestore(SortedSetKey::new("page"), ZRange::by_lex("[a", "+"))),
+                   ["ZRANGESTORE", "page", "board", "[a", "+", "BYLEX"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_zrangestore() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let board = || SortedSetKey::new("leaderboard5");
+        let page = || SortedSetKey::new("leaderboard5:top");
+        board().del().query::<()>(&mut con)?;
+        board().zadd_many([(1.0, "a"), (5.0, "b"), (3.0, "c"), (4.0, "d")])
+            .query::<()>(&mut con)?;
+
+        let stored: usize = board()
+            .zrangestore(page(), ZRange::by_rank(0, 2).rev())
+            .query(&mut con)?;
+        assert_eq!(3, stored);
+        let members: Vec<String> = page().zrange(ZRange::by_rank(0, -1).rev())
+            .query(&mut con)?;
+        assert_eq!(vec!["b", "d", "c"], members);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_hget_opt() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let hash = || HashKey::new("hash1");
+        hash().del().query::<()>(&mut con)?;
+        let missing: Option<String> = hash().hget_opt("absent", &mut con)?;
+        assert_eq!(None, missing);
+
+        assert!(hash().hsetnx_ok("f", "first", &mut con)?);
+        assert!(!hash().hsetnx_ok("f", "second", &mut con)?);
+        let value: Option<String> = hash().hget_opt("f", &mut con)?;
+        assert_eq!(Some("first".to_string()), value);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "test-helpers")]
+    fn test_flush_args() {
+        assert_eq!(arg_strings(&flushdb(true)), ["FLUSHDB", "ASYNC"]);
+        assert_eq!(arg_strings(&flushall(false)), ["FLUSHALL", "SYNC"]);
+    }
+
+    #[test]
+    #[cfg(feature = "test-helpers")]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_flushdb() -> Result<(), Box<dyn std::error::Error>> {
+        // a database of its own, so the other tests' keys survive
+        let client = redis::Client::open("redis://127.0.0.1/15")?;
+        let mut con = client.get_connection()?;
+
+        StringKey::new("flushed1").set("x").query::<()>(&mut con)?;
+        SetKey::new("flushed2").sadd("x").query::<()>(&mut con)?;
+        let size: usize = dbsize().query(&mut con)?;
+        assert!(size >= 2);
+
+        flushdb(false).query::<()>(&mut con)?;
+        let size: usize = dbsize().query(&mut con)?;
+        assert_eq!(0, size);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_builder() {
+        let key = || StringKey::new("k");
+        let cmd = key().set_with("v").build().unwrap();
+        assert_eq!(render(&cmd), "SET k v");
+        let cmd = key().set_with("v").exat(1700000000).build().unwrap();
+        assert_eq!(render(&cmd), "SET k v EXAT 1700000000");
+        let cmd = key().set_with("v").pxat(1700000000000).build().unwrap();
+        assert_eq!(render(&cmd), "SET k v PXAT 1700000000000");
+        let cmd = key().set_with("v").keep_ttl().build().unwrap();
+        assert_eq!(render(&cmd), "SET k v KEEPTTL");
+
+        let err = key().set_with("v").ex(10).exat(1700000000).build().err();
+        assert_eq!(Some(Error::ConflictingOptions(vec!["EX", "EXAT"])), err);
+        assert!(key().set_with("v").px(10).keep_ttl().build().is_err());
+    }
+
+    #[test]
+    #[ignore = "requires a Redis 7.0 server at 127.0.0.1"]
+    fn test_set_exat() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let key = || StringKey::new("expiring_at1");
+        key().set_with("v").exat(now + 60).build()?.query::<()>(&mut con)?;
+        let expires_at: u64 = key().expiretime().query(&mut con)?;
+        assert_eq!(now + 60, expires_at);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zdiff_args() {
+        let keys = || vec![SortedSetKey::new("a"), SortedSetKey::new("b")].into_iter();
+        assert_eq!(arg_strings(&SortedSetKey::zdiff(keys(), false)),
+                   ["ZDIFF", "2", "a", "b"]);
+        assert_eq!(arg_strings(&SortedSetKey::zdiff(keys(), true)),
+                   ["ZDIFF", "2", "a", "b", "WITHSCORES"]);
+        assert_eq!(arg_strings(&SortedSetKey::zdiffstore(SortedSetKey::new("out"), keys())),
+                   ["ZDIFFSTORE", "out", "2", "a", "b"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_zdiff() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let a = || SortedSetKey::new("zdiff_a");
+        let b = || SortedSetKey::new("zdiff_b");
+        a().del().query::<()>(&mut con)?;
+        b().del().query::<()>(&mut con)?;
+        a().zadd_many([(1.0, "x"), (2.0, "y"), (3.0, "z")]).query::<()>(&mut con)?;
+        b().zadd_many([(9.0, "y")]).query::<()>(&mut con)?;
+
+        let diff: Vec<(String, f64)> =
+            SortedSetKey::zdiff(vec![a(), b()].into_iter(), true).query(&mut con)?;
+        assert_eq!(vec![("x".to_string(), 1.0), ("z".to_string(), 3.0)], diff);
+
+        let out = || SortedSetKey::new("zdiff_out");
+        let stored: usize = SortedSetKey::zdiffstore(out(), vec![a(), b()].into_iter())
             .query(&mut con)?;
+        assert_eq!(2, stored);
+        let members: Vec<String> = out().zrange(ZRange::by_rank(0, -1)).query(&mut con)?;
+        assert_eq!(vec!["x", "z"], members);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_kind_classify() {
+        assert_eq!(None, KeyKind::classify(RedisType::None, None));
+        assert_eq!(Some(KeyKind::Int),
+                   KeyKind::classify(RedisType::String, Some(Encoding::Int)));
+        assert_eq!(Some(KeyKind::EmbStr),
+                   KeyKind::classify(RedisType::String, Some(Encoding::EmbStr)));
+        assert_eq!(Some(KeyKind::RawString),
+                   KeyKind::classify(RedisType::String, Some(Encoding::Raw)));
+        assert_eq!(Some(KeyKind::Set),
+                   KeyKind::classify(RedisType::Set, Some(Encoding::IntSet)));
+        assert_eq!(Some(KeyKind::Other("ReJSON-RL".to_string())),
+                   KeyKind::classify(RedisType::Other("ReJSON-RL".to_string()), None));
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_inspect() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let counter = || IntKey::new("inspect_counter");
+        let text = || StringKey::new("inspect_text");
+        counter().del().query::<()>(&mut con)?;
+        counter().incr(42).query::<()>(&mut con)?;
+        text().set("x".repeat(100)).query::<()>(&mut con)?;
+
+        assert_eq!(Some(KeyKind::Int), inspect("inspect_counter", &mut con)?);
+        assert_eq!(Some(KeyKind::RawString), inspect("inspect_text", &mut con)?);
+        assert_eq!(None, inspect("inspect_missing", &mut con)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_swap_with() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let blue = || StringKey::new("swap_blue");
+        let green = || StringKey::new("swap_green");
+        blue().set("b").query::<()>(&mut con)?;
+        green().set("g").query::<()>(&mut con)?;
+
+        blue().swap_with(green(), &mut con)?;
+        let b: String = blue().get::<String>().query(&mut con)?;
+        let g: String = green().get::<String>().query(&mut con)?;
+        assert_eq!(("g", "b"), (b.as_str(), g.as_str()));
+
+        green().del().query::<()>(&mut con)?;
+        blue().swap_with(green(), &mut con)?;
+        let b: Option<String> = blue().get::<String>().query(&mut con)?;
+        let g: Option<String> = green().get::<String>().query(&mut con)?;
+        assert_eq!((None, Some("g".to_string())), (b, g));
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_sample() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let buckets = || SetKey::new("sample_buckets");
+        buckets().del().query::<()>(&mut con)?;
+        buckets().sadd_many(0..10).query::<()>(&mut con)?;
+
+        let sample: Vec<u32> = buckets().sample(5, &mut con)?;
+        assert_eq!(5, sample.len());
+        assert_eq!(5, sample.iter().collect::<HashSet<_>>().len());
+        assert!(sample.iter().all(|&m| m < 10));
+
+        let repeated: Vec<u32> = buckets().sample_with_replacement(20, &mut con)?;
+        assert_eq!(20, repeated.len());
+        assert!(repeated.iter().all(|&m| m < 10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitcount_range_args() {
+        let bits = || BitmapKey::new("bits");
+        assert_eq!(arg_strings(&bits().bitcount_range(0, -1, BitUnit::Byte)),
+                   ["BITCOUNT", "bits", "0", "-1", "BYTE"]);
+        assert_eq!(arg_strings(&bits().bitcount_range(3, 9, BitUnit::Bit)),
+                   ["BITCOUNT", "bits", "3", "9", "BIT"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis 7.0 server at 127.0.0.1"]
+    fn test_bitcount_range() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let bits = || BitmapKey::new("bitcount_range1");
+        bits().del().query::<()>(&mut con)?;
+        for offset in [1, 4, 5, 12] {
+            bits().setbit(offset, true).query::<()>(&mut con)?;
+        }
+
+        let in_window: usize = bits().bitcount_range(4, 11, BitUnit::Bit).query(&mut con)?;
+        assert_eq!(2, in_window);
+        let first_byte: usize = bits().bitcount_range(0, 0, BitUnit::Byte).query(&mut con)?;
+        assert_eq!(3, first_byte);
+        let total: usize = bits().bitcount().query(&mut con)?;
+        assert_eq!(4, total);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_mget_map() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        StringKey::new("mget_map_a").set("1").query::<()>(&mut con)?;
+        StringKey::new("mget_map_b").set("2").query::<()>(&mut con)?;
+        StringKey::new("mget_map_c").del().query::<()>(&mut con)?;
+
+        let keys = ["mget_map_a", "mget_map_c", "mget_map_b"].into_iter().map(StringKey::new);
+        let values: HashMap<&str, Option<String>> = mget_map(keys, &mut con)?;
+        assert_eq!(3, values.len());
+        assert_eq!(Some("1".to_string()), values["mget_map_a"]);
+        assert_eq!(Some("2".to_string()), values["mget_map_b"]);
+        assert_eq!(None, values["mget_map_c"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mpop_args() {
+        let lists = vec![ListKey::new("a"), ListKey::new("b")];
+        assert_eq!(arg_strings(&ListKey::lmpop(lists.into_iter(), Side::Right, 3)),
+                   ["LMPOP", "2", "a", "b", "RIGHT", "COUNT", "3"]);
+        let sets = vec![SortedSetKey::new("z")];
+        assert_eq!(arg_strings(&SortedSetKey::zmpop(sets.into_iter(), ScoreEnd::Min, 1)),
+                   ["ZMPOP", "1", "z", "MIN", "COUNT", "1"]);
+    }
+
+    #[test]
+    fn test_zmpop_reply() {
+        let reply = Value::Bulk(vec![
+            Value::Data(b"z".to_vec()),
+            Value::Bulk(vec![
+                Value::Bulk(vec![Value::Data(b"a".to_vec()), Value::Data(b"1".to_vec())]),
+                Value::Bulk(vec![Value::Data(b"b".to_vec()), Value::Data(b"2.5".to_vec())]),
+            ]),
+        ]);
+        type Popped = Option<(String, Vec<Vec<(String, f64)>>)>;
+        let popped: Popped = FromRedisValue::from_redis_value(&reply).unwrap();
+        assert_eq!(Some(("z".to_string(), vec![vec![("a".to_string(), 1.0)],
+                                               vec![("b".to_string(), 2.5)]])),
+                   popped);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis 7.0 server at 127.0.0.1"]
+    fn test_lmpop() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let empty = || ListKey::new("lmpop_empty");
+        let jobs = || ListKey::new("lmpop_jobs");
+        empty().del().query::<()>(&mut con)?;
+        jobs().del().query::<()>(&mut con)?;
+        jobs().rpush(&["j1", "j2", "j3"]).query::<()>(&mut con)?;
+
+        let popped: Option<(String, Vec<String>)> =
+            ListKey::lmpop(vec![empty(), jobs()].into_iter(), Side::Left, 2)
+                .query(&mut con)?;
+        assert_eq!(Some(("lmpop_jobs".to_string(), vec!["j1".to_string(), "j2".to_string()])),
+                   popped);
+
+        jobs().del().query::<()>(&mut con)?;
+        let popped: Option<(String, Vec<String>)> =
+            ListKey::lmpop(vec![empty(), jobs()].into_iter(), Side::Left, 2)
+                .query(&mut con)?;
+        assert_eq!(None, popped);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_require_keys() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        HashKey::new("config:limits").hset("max", 10).query::<()>(&mut con)?;
+        HashKey::new("config:flags").hset("beta", 1).query::<()>(&mut con)?;
+        HashKey::new("config:regions").del().query::<()>(&mut con)?;
+
+        let keys = ["config:limits", "config:regions", "config:flags"];
+        let missing = require_keys(keys.into_iter().map(HashKey::new), &mut con)?;
+        assert_eq!(Err(vec![MissingKey { key: "config:regions", redis_type: RedisType::Hash }]),
+                   missing);
+
+        let present = require_keys(keys[..1].iter().copied().map(HashKey::new), &mut con)?;
+        assert_eq!(Ok(()), present);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_hash_fields_args() {
+        let field = vec![b'f', 0, 0xff];
+        let cmd = HashKey::new("bin").hset(&field, &b"v\x00"[..]);
+        assert_eq!(args(&cmd), [b"HSET".to_vec(), b"bin".to_vec(), field, b"v\x00".to_vec()]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_binary_hash_fields() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let map = || HashKey::new("binary_map1");
+        let field = vec![b'f', 0, 0xff];
+        map().del().query::<()>(&mut con)?;
+        map().hset(&field, &b"v\x00"[..]).query::<()>(&mut con)?;
+
+        let value: Vec<u8> = map().hget(&field).query(&mut con)?;
+        assert_eq!(b"v\x00".to_vec(), value);
+        let all: HashMap<Vec<u8>, Vec<u8>> = map().hgetall().query(&mut con)?;
+        assert_eq!(HashMap::from([(field, b"v\x00".to_vec())]), all);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_rate_limiter() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        SortedSetKey::new("rate:user1").del().query::<()>(&mut con)?;
+        let limiter = RateLimiter::new(SortedSetKey::new("rate:user1"));
+
+        for now in [1000, 1000, 1500] {
+            assert!(limiter.allow(now, 1000, 3, &mut con)?);
+        }
+        assert!(!limiter.allow(1800, 1000, 3, &mut con)?);
+        // the two requests at 1000 have left the window
+        assert!(limiter.allow(2000, 1000, 3, &mut con)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_jittered_ttl() {
+        assert_eq!(107, jittered_ttl(100, 10, &mut || 7));
+        assert_eq!(100, jittered_ttl(100, 10, &mut || 11));
+        assert_eq!(100, jittered_ttl(100, 0, &mut || 12345));
+        assert_eq!(usize::MAX, jittered_ttl(usize::MAX, usize::MAX, &mut || 1));
+        let ttl = jittered_ttl(60, 30, &mut StdRandom);
+        assert!((60..=90).contains(&ttl));
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_expire_jittered() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let cached = || StringKey::new("jittered1");
+        cached().set("v").query::<()>(&mut con)?;
+        assert!(cached().expire_jittered_with(100, 10, &mut || 7, &mut con)?);
+        let ttl: i64 = cached().ttl().query(&mut con)?;
+        assert!((100..=107).contains(&ttl));
+
+        assert!(cached().expire_jittered(100, 10, &mut con)?);
+        let ttl: i64 = cached().ttl().query(&mut con)?;
+        assert!((99..=110).contains(&ttl));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let reply = "# Server\r\nredis_version:7.2.4\r\nexecutable:/usr/bin/redis-server\r\n\
+                     \r\n# Clients\r\nconnected_clients:3\r\n\r\n\
+                     # Keyspace\r\ndb0:keys=5,expires=1,avg_ttl=0\r\n";
+        let info = parse_info(reply);
+        assert_eq!(4, info.len());
+        assert_eq!("7.2.4", info["redis_version"]);
+        assert_eq!("3", info["connected_clients"]);
+        assert_eq!("/usr/bin/redis-server", info["executable"]);
+        assert_eq!("keys=5,expires=1,avg_ttl=0", info["db0"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_info() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let server = info(Some("server"), &mut con)?;
+        assert!(server.contains_key("redis_version"));
+        assert!(!server.contains_key("connected_clients"));
+        let all = info(None, &mut con)?;
+        assert!(all.contains_key("connected_clients"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expire_with_args() {
+        assert_eq!(arg_strings(&StringKey::new("k").expire_with(60, ExpireOption::Gt)),
+                   ["EXPIRE", "k", "60", "GT"]);
+    }
+
+    #[test]
+    #[ignore = "requires a Redis server at 127.0.0.1"]
+    fn test_with_redis() -> Result<(), Box<dyn std::error::Error>> {
+        let client = redis::Client::open("redis://127.0.0.1/")?;
+        let mut con = client.get_connection()?;
+
+        let s = Schema;
+        s.myset1().del().query::<()>(&mut con)?;
+        s.myset1().sadd("first").query::<()>(&mut con)?;
+        let members: Vec<String> =
+            s.myset1().smembers().query(&mut con)?;
+
+        assert_eq!(vec!["first"], members);
+
+        Cmd::sadd(&["test_first", "test_second"], "member")
+            .query::<()>(&mut con)?;
 
         Ok(())
     }